@@ -52,6 +52,15 @@ impl BBox3D {
         self.pose * point
     }
 
+    /// The box grown by `margin` on both sides along its x, y and z
+    /// axes. Negative margins shrink it, down to nothing.
+    pub fn enlarged(&self, margin: &na::Vector3<f64>) -> Self {
+        Self {
+            extents: (self.extents + margin * 2.0).sup(&na::Vector3::zeros()),
+            pose: self.pose,
+        }
+    }
+
     pub fn vertices(&self) -> Vec<na::Point3<f64>> {
         (0b000..=0b111)
             .map(|mask| self.vertex(mask & 0b001 != 0, mask & 0b010 != 0, mask & 0b100 != 0))
@@ -0,0 +1,291 @@
+use crate::bbox::BBox3D;
+use nalgebra as na;
+use rayon::prelude::*;
+use std::collections::HashMap;
+
+/// The side of a grid cell in metres, about the length of a car.
+const CELL_SIZE: f64 = 4.0;
+
+/// How far the grid reaches from the origin along x and y in metres,
+/// well beyond the range of the sensors. Boxes are only registered in
+/// the cells within it, so a huge or misplaced box cannot blow up the
+/// grid, and points beyond it lie in no box.
+const GRID_EXTENT: f64 = 256.0;
+
+/// A bird's-eye-view grid over a set of 3D boxes, to find the boxes
+/// containing a point without testing every box.
+#[derive(Debug, Clone)]
+pub struct BoxIndex {
+    boxes: Vec<IndexedBox>,
+    // The boxes whose footprint overlaps each cell of the xy grid
+    cells: HashMap<(i64, i64), Vec<usize>>,
+}
+
+#[derive(Debug, Clone)]
+struct IndexedBox {
+    world_to_box: na::Isometry3<f64>,
+    half_extents: na::Vector3<f64>,
+}
+
+/// Which boxes the points of a cloud lie in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoxMembership {
+    /// The first box containing each point, if any.
+    pub point_boxes: Vec<Option<usize>>,
+    /// The number of points inside each box. A point in overlapping
+    /// boxes counts for all of them.
+    pub box_counts: Vec<usize>,
+}
+
+impl BoxIndex {
+    /// Indexes boxes, each with a margin in metres by which it is
+    /// enlarged on both sides along its x, y and z axes.
+    pub fn new<'a, I>(boxes: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a BBox3D, na::Vector3<f64>)>,
+    {
+        let boxes: Vec<_> = boxes
+            .into_iter()
+            .map(|(bbox, margin)| IndexedBox {
+                world_to_box: bbox.pose.inverse(),
+                half_extents: bbox.enlarged(&margin).extents / 2.0,
+            })
+            .collect();
+
+        let grid_cells = (GRID_EXTENT / CELL_SIZE) as i64;
+        let mut cells: HashMap<_, Vec<_>> = HashMap::new();
+        for (idx, indexed) in boxes.iter().enumerate() {
+            // Kept in `boxes` so that the indices match the input, but
+            // with NaN or infinite values it has no cells and no points.
+            if !indexed.is_finite() {
+                continue;
+            }
+            let box_to_world = indexed.world_to_box.inverse();
+            let half = indexed.half_extents;
+            let (min, max) = (0b000..=0b111)
+                .map(|mask: u8| {
+                    let corner = na::Point3::new(
+                        if mask & 0b001 != 0 { half.x } else { -half.x },
+                        if mask & 0b010 != 0 { half.y } else { -half.y },
+                        if mask & 0b100 != 0 { half.z } else { -half.z },
+                    );
+                    box_to_world * corner
+                })
+                .fold(
+                    (
+                        na::Point2::from([f64::INFINITY; 2]),
+                        na::Point2::from([f64::NEG_INFINITY; 2]),
+                    ),
+                    |(min, max), corner| (min.inf(&corner.xy()), max.sup(&corner.xy())),
+                );
+            let clamp = |(i, j): (i64, i64)| {
+                (
+                    i.clamp(-grid_cells, grid_cells),
+                    j.clamp(-grid_cells, grid_cells),
+                )
+            };
+            let (min_cell, max_cell) = (clamp(cell_of(&min)), clamp(cell_of(&max)));
+            for i in min_cell.0..=max_cell.0 {
+                for j in min_cell.1..=max_cell.1 {
+                    cells.entry((i, j)).or_default().push(idx);
+                }
+            }
+        }
+
+        Self { boxes, cells }
+    }
+
+    pub fn len(&self) -> usize {
+        self.boxes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.boxes.is_empty()
+    }
+
+    /// The boxes containing a point, in the order they were given.
+    pub fn boxes_containing<'a>(
+        &'a self,
+        point: &na::Point3<f64>,
+    ) -> impl Iterator<Item = usize> + 'a {
+        let point = *point;
+        self.cells
+            .get(&cell_of(&point.xy()))
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |&idx| {
+                let IndexedBox {
+                    world_to_box,
+                    half_extents,
+                } = &self.boxes[idx];
+                let local = world_to_box * point;
+                local.x.abs() < half_extents.x
+                    && local.y.abs() < half_extents.y
+                    && local.z.abs() < half_extents.z
+            })
+    }
+
+    /// Finds the boxes of all points in one parallel pass. The points
+    /// are `[x, y, z, intensity]` records as stored in velodyne scans.
+    pub fn membership(&self, points: &[[f32; 4]]) -> BoxMembership {
+        let containing: Vec<Vec<usize>> = points
+            .par_iter()
+            .map(|&[x, y, z, _]| {
+                let point = na::Point3::new(x, y, z).cast();
+                self.boxes_containing(&point).collect()
+            })
+            .collect();
+
+        let mut box_counts = vec![0; self.boxes.len()];
+        for &idx in containing.iter().flatten() {
+            box_counts[idx] += 1;
+        }
+        let point_boxes = containing
+            .iter()
+            .map(|boxes| boxes.first().copied())
+            .collect();
+
+        BoxMembership {
+            point_boxes,
+            box_counts,
+        }
+    }
+}
+
+impl IndexedBox {
+    fn is_finite(&self) -> bool {
+        self.half_extents.iter().all(|value| value.is_finite())
+            && self
+                .world_to_box
+                .to_homogeneous()
+                .iter()
+                .all(|value| value.is_finite())
+    }
+}
+
+fn cell_of(point: &na::Point2<f64>) -> (i64, i64) {
+    (
+        (point.x / CELL_SIZE).floor() as i64,
+        (point.y / CELL_SIZE).floor() as i64,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(center: [f64; 3], extents: [f64; 3], roll: f64, yaw: f64) -> BBox3D {
+        BBox3D {
+            extents: na::Vector3::from(extents),
+            pose: na::Isometry3::from_parts(
+                na::Translation3::from(na::Vector3::from(center)),
+                na::UnitQuaternion::from_euler_angles(roll, 0.0, yaw),
+            ),
+        }
+    }
+
+    /// Tests every box for every point.
+    fn brute_force(
+        boxes: &[(BBox3D, na::Vector3<f64>)],
+        points: &[na::Point3<f64>],
+    ) -> BoxMembership {
+        let inside = |(bbox, margin): &(BBox3D, na::Vector3<f64>), point: &na::Point3<f64>| {
+            let half_extents = bbox.enlarged(margin).extents / 2.0;
+            let local = bbox.pose.inverse() * point;
+            (0..3).all(|axis| local[axis].abs() < half_extents[axis])
+        };
+        BoxMembership {
+            point_boxes: points
+                .iter()
+                .map(|point| boxes.iter().position(|entry| inside(entry, point)))
+                .collect(),
+            box_counts: boxes
+                .iter()
+                .map(|entry| points.iter().filter(|point| inside(entry, point)).count())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn membership_matches_brute_force() {
+        let deg = f64::to_radians;
+        let boxes = vec![
+            // Straddles the cell corner at the origin
+            (
+                bbox([0.1, -0.2, -1.0], [4.5, 1.8, 1.5], 0.0, deg(30.0)),
+                na::Vector3::zeros(),
+            ),
+            // Long, turned and overlapping the first box
+            (
+                bbox([-2.5, 1.0, -0.5], [12.0, 2.5, 3.0], 0.0, deg(100.0)),
+                na::Vector3::new(0.1, 0.2, 0.1),
+            ),
+            // Rolled, across the cell edges at x = -4 and y = -8
+            (
+                bbox([-4.0, -8.1, 0.5], [3.0, 2.0, 2.0], deg(20.0), deg(-45.0)),
+                na::Vector3::repeat(0.3),
+            ),
+            // Shrunk to nothing by a negative margin
+            (
+                bbox([7.9, 4.1, 0.0], [1.0, 1.0, 1.0], 0.0, 0.0),
+                na::Vector3::repeat(-1.0),
+            ),
+        ];
+        let records: Vec<[f32; 4]> = (0..70)
+            .flat_map(|i| (0..70).map(move |j| (i, j)))
+            .flat_map(|(i, j)| {
+                (0..9).map(move |k| {
+                    [
+                        -13.0 + i as f32 * 0.37,
+                        -13.0 + j as f32 * 0.37,
+                        -2.5 + k as f32 * 0.6,
+                        0.5,
+                    ]
+                })
+            })
+            .collect();
+        let points: Vec<na::Point3<f64>> = records
+            .iter()
+            .map(|&[x, y, z, _]| na::Point3::new(x, y, z).cast())
+            .collect();
+
+        let index = BoxIndex::new(boxes.iter().map(|(bbox, margin)| (bbox, *margin)));
+        let membership = index.membership(&records);
+        let expected = brute_force(&boxes, &points);
+
+        assert_eq!(index.len(), boxes.len());
+        assert_eq!(membership, expected);
+        assert!(expected.box_counts[..3].iter().all(|&count| count > 0));
+        assert_eq!(expected.box_counts[3], 0);
+        assert!(points
+            .iter()
+            .any(|point| index.boxes_containing(point).count() > 1));
+    }
+
+    #[test]
+    fn degenerate_boxes() {
+        let boxes = [
+            bbox([1.0, 1.0, 0.0], [f64::NAN, 2.0, 2.0], 0.0, 0.0),
+            bbox([f64::INFINITY, 0.0, 0.0], [2.0, 2.0, 2.0], 0.0, 0.0),
+            bbox([0.0, 0.0, 0.0], [2.0, 2.0, 2.0], 0.0, f64::NAN),
+            bbox([0.0, 0.0, 0.0], [1e12, 1e12, 2.0], 0.0, 0.0),
+            bbox([1.0, 1.0, 0.0], [2.0, 2.0, 2.0], 0.0, 0.0),
+        ];
+        let index = BoxIndex::new(boxes.iter().map(|bbox| (bbox, na::Vector3::zeros())));
+        let membership = index.membership(&[[1.0, 1.0, 0.0, 0.0], [100.0, -200.0, 0.0, 0.0]]);
+
+        assert_eq!(index.len(), boxes.len());
+        assert_eq!(membership.point_boxes, [Some(3), Some(3)]);
+        assert_eq!(membership.box_counts, [0, 0, 0, 2, 1]);
+    }
+
+    #[test]
+    fn empty_index() {
+        let index = BoxIndex::new([]);
+        assert!(index.is_empty());
+        let membership = index.membership(&[[-1.0, 2.0, 0.0, 0.0]]);
+        assert_eq!(membership.point_boxes, [None]);
+        assert!(membership.box_counts.is_empty());
+    }
+}
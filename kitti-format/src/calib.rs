@@ -1,34 +1,66 @@
+use crate::storage::Storage;
 use nalgebra as na;
-use std::{fs::File, io, io::BufRead, path::PathBuf};
+use std::{
+    fs::File,
+    io,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
 #[derive(Clone)]
 pub struct KittiCalib {
     // Coordinate transformation from rectified camera (3D) to camera image (2D)
     pub p0: na::Matrix3x4<f64>,
+    // Projections of the other cameras, where P2 is for the left colour
+    // camera of `image_2` and P3 for the right one of `image_3`
+    pub p1: na::Matrix3x4<f64>,
+    pub p2: na::Matrix3x4<f64>,
+    pub p3: na::Matrix3x4<f64>,
     // Coordinate transformation from Lidar to rectified camera
     pub velo_to_cam: na::Matrix3x4<f64>,
     // Coordinate transformation from rectified camera to camera
     pub r0_rect: na::Matrix3<f64>,
+    // The lines of other entries, such as `Tr_imu_to_velo`, kept to be
+    // written back
+    pub other_lines: Vec<String>,
 }
 
 impl KittiCalib {
     pub fn from_file(calib_path: PathBuf) -> Self {
         let file = File::open(calib_path.clone()).expect(&format!("{:?} not exists!", calib_path));
-        let content_lines = io::BufReader::new(file).lines();
+        Self::from_reader(io::BufReader::new(file))
+    }
+
+    pub fn from_storage(storage: &dyn Storage, calib_path: &Path) -> io::Result<Self> {
+        let file = storage.open(calib_path)?;
+        Ok(Self::from_reader(io::BufReader::new(file)))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> Self {
+        let content_lines = reader.lines();
         let mut p0 = na::Matrix3x4::default();
+        let mut p1 = na::Matrix3x4::default();
+        let mut p2 = na::Matrix3x4::default();
+        let mut p3 = na::Matrix3x4::default();
         let mut velo_to_cam = na::Matrix3x4::default();
         let mut r0_rect = na::Matrix3::default();
+        let mut other_lines = vec![];
         for line in content_lines {
             if let Ok(line) = line {
                 let mut words: Vec<&str> = line.split(&[' ', ':'][..]).collect();
                 words.retain(|s| !s.is_empty());
                 match words.first() {
-                    Some(&"P0") => {
+                    Some(&name @ ("P0" | "P1" | "P2" | "P3")) => {
                         let vals: Vec<f64> = words[1..]
                             .iter()
                             .map(|s| s.parse::<f64>().unwrap())
                             .collect();
                         let mat = na::Matrix3x4::from_row_slice(&vals);
-                        p0 = mat;
+                        match name {
+                            "P0" => p0 = mat,
+                            "P1" => p1 = mat,
+                            "P2" => p2 = mat,
+                            _ => p3 = mat,
+                        }
                     }
                     Some(&"Tr_velo_to_cam") => {
                         let vals: Vec<f64> = words[1..]
@@ -46,38 +78,220 @@ impl KittiCalib {
                         let mat = na::Matrix3::from_row_slice(&vals);
                         r0_rect = mat;
                     }
-                    _ => {}
+                    Some(_) => other_lines.push(line.clone()),
+                    None => {}
                 }
             }
         }
         KittiCalib {
             p0,
+            p1,
+            p2,
+            p3,
             velo_to_cam,
             r0_rect,
+            other_lines,
         }
     }
     pub fn get_transformation_from_rectified_camera_to_velodyne(&self) -> na::Isometry3<f64> {
-        let rect2velo = {
-            let rect_to_cam = na::UnitQuaternion::from_matrix(&self.r0_rect).inverse();
-            let velo_to_cam_rot = na::UnitQuaternion::from_matrix(&na::Matrix3::from_row_slice(&[
-                self.velo_to_cam.m11,
-                self.velo_to_cam.m12,
-                self.velo_to_cam.m13,
-                self.velo_to_cam.m21,
-                self.velo_to_cam.m22,
-                self.velo_to_cam.m23,
-                self.velo_to_cam.m31,
-                self.velo_to_cam.m32,
-                self.velo_to_cam.m33,
-            ]));
-            let velo_to_cam_trans = na::Translation3::from([
-                self.velo_to_cam.m14,
-                self.velo_to_cam.m24,
-                self.velo_to_cam.m34,
-            ]);
-            let velo_to_cam = na::Isometry3::from_parts(velo_to_cam_trans, velo_to_cam_rot);
-            velo_to_cam.inverse() * rect_to_cam
+        let rect_to_cam = na::UnitQuaternion::from_matrix(&self.r0_rect).inverse();
+        self.velo_to_cam_isometry().inverse() * rect_to_cam
+    }
+
+    /// `Tr_velo_to_cam` as an isometry.
+    pub fn velo_to_cam_isometry(&self) -> na::Isometry3<f64> {
+        let rotation = na::UnitQuaternion::from_matrix(
+            &self.velo_to_cam.fixed_slice::<3, 3>(0, 0).into_owned(),
+        );
+        let translation = na::Translation3::from(self.velo_to_cam.column(3).into_owned());
+        na::Isometry3::from_parts(translation, rotation)
+    }
+
+    pub fn set_velo_to_cam_isometry(&mut self, velo_to_cam: &na::Isometry3<f64>) {
+        self.velo_to_cam = velo_to_cam
+            .to_homogeneous()
+            .fixed_slice::<3, 4>(0, 0)
+            .into_owned();
+    }
+
+    pub fn write_file<P: AsRef<Path>>(&self, calib_path: P) -> io::Result<()> {
+        let mut file = io::BufWriter::new(File::create(calib_path)?);
+        self.write(&mut file)?;
+        file.flush()
+    }
+
+    /// Writes the projections, `R0_rect` and `Tr_velo_to_cam` in the
+    /// layout of the object benchmark, followed by the other entries of
+    /// the original file, such as `Tr_imu_to_velo`, as they were.
+    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let mut write_line = |name: &str, values: Vec<f64>| -> io::Result<()> {
+            let values: Vec<String> = values.iter().map(|value| format!("{value:e}")).collect();
+            writeln!(writer, "{name}: {}", values.join(" "))
         };
-        rect2velo
+        // Matrices are stored column by column, so the transpose gives
+        // the values row by row.
+        write_line("P0", self.p0.transpose().as_slice().to_vec())?;
+        write_line("P1", self.p1.transpose().as_slice().to_vec())?;
+        write_line("P2", self.p2.transpose().as_slice().to_vec())?;
+        write_line("P3", self.p3.transpose().as_slice().to_vec())?;
+        write_line("R0_rect", self.r0_rect.transpose().as_slice().to_vec())?;
+        write_line(
+            "Tr_velo_to_cam",
+            self.velo_to_cam.transpose().as_slice().to_vec(),
+        )?;
+        for line in &self.other_lines {
+            writeln!(writer, "{line}")?;
+        }
+        Ok(())
+    }
+
+    /// The distance between the colour cameras in metres.
+    pub fn stereo_baseline(&self) -> f64 {
+        (self.p2.m14 - self.p3.m14) / self.p2.m11
+    }
+
+    /// The projection matrix of a camera.
+    pub fn projection(&self, camera: KittiCamera) -> &na::Matrix3x4<f64> {
+        match camera {
+            KittiCamera::Cam0 => &self.p0,
+            KittiCamera::Cam1 => &self.p1,
+            KittiCamera::Cam2 => &self.p2,
+            KittiCamera::Cam3 => &self.p3,
+        }
+    }
+}
+
+/// The cameras of the KITTI rig. `Cam0` and `Cam1` are the grey stereo
+/// pair, `Cam2` and `Cam3` the colour one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KittiCamera {
+    Cam0,
+    Cam1,
+    Cam2,
+    Cam3,
+}
+
+impl KittiCamera {
+    /// The directory of the images of the camera, e.g. `image_2`.
+    pub fn image_dir(&self) -> &'static str {
+        match self {
+            KittiCamera::Cam0 => "image_0",
+            KittiCamera::Cam1 => "image_1",
+            KittiCamera::Cam2 => "image_2",
+            KittiCamera::Cam3 => "image_3",
+        }
+    }
+}
+
+/// The mounting of one LiDAR of a multi-LiDAR rig, read from a file
+/// with a `Tr_lidar_to_vehicle` line of 12 row-major values.
+#[derive(Clone)]
+pub struct LidarExtrinsic {
+    // Coordinate transformation from the LiDAR to the common vehicle frame
+    pub lidar_to_vehicle: na::Matrix3x4<f64>,
+}
+
+impl LidarExtrinsic {
+    pub fn identity() -> Self {
+        Self {
+            lidar_to_vehicle: na::Matrix3x4::identity(),
+        }
+    }
+
+    pub fn from_storage(storage: &dyn Storage, path: &Path) -> io::Result<Self> {
+        let file = storage.open(path)?;
+        Self::from_reader(io::BufReader::new(file))
+    }
+
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        for line in reader.lines() {
+            let line = line?;
+            let Some(values) = line.strip_prefix("Tr_lidar_to_vehicle:") else {
+                continue;
+            };
+            let vals: Vec<f64> = values
+                .split_whitespace()
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<_, _>>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            if vals.len() != 12 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "expect 12 values for Tr_lidar_to_vehicle, but found {}",
+                        vals.len()
+                    ),
+                ));
+            }
+            return Ok(Self {
+                lidar_to_vehicle: na::Matrix3x4::from_row_slice(&vals),
+            });
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing Tr_lidar_to_vehicle",
+        ))
+    }
+
+    pub fn to_isometry(&self) -> na::Isometry3<f64> {
+        let rotation = na::UnitQuaternion::from_matrix(
+            &self.lidar_to_vehicle.fixed_slice::<3, 3>(0, 0).into_owned(),
+        );
+        let translation = na::Translation3::from(self.lidar_to_vehicle.column(3).into_owned());
+        na::Isometry3::from_parts(translation, rotation)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemStorage;
+
+    const CALIB: &str = "\
+P0: 7.070493e+02 0.000000e+00 6.040814e+02 0.000000e+00 0.000000e+00 7.070493e+02 1.805066e+02 0.000000e+00 0.000000e+00 0.000000e+00 1.000000e+00 0.000000e+00
+P1: 7.070493e+02 0.000000e+00 6.040814e+02 -3.797842e+02 0.000000e+00 7.070493e+02 1.805066e+02 0.000000e+00 0.000000e+00 0.000000e+00 1.000000e+00 0.000000e+00
+P2: 7.070493e+02 0.000000e+00 6.040814e+02 4.575831e+01 0.000000e+00 7.070493e+02 1.805066e+02 -3.454157e-01 0.000000e+00 0.000000e+00 1.000000e+00 4.981016e-03
+P3: 7.070493e+02 0.000000e+00 6.040814e+02 -3.341081e+02 0.000000e+00 7.070493e+02 1.805066e+02 2.330660e+00 0.000000e+00 0.000000e+00 1.000000e+00 3.201153e-03
+R0_rect: 9.999128e-01 1.009263e-02 -8.511932e-03 -1.012729e-02 9.999406e-01 -4.037671e-03 8.470675e-03 4.123522e-03 9.999556e-01
+Tr_velo_to_cam: 6.927964e-03 -9.999722e-01 -2.757829e-03 -2.457729e-02 -1.162982e-03 2.749836e-03 -9.999955e-01 -6.127237e-02 9.999753e-01 6.931141e-03 -1.143899e-03 -3.321029e-01
+Tr_imu_to_velo: 9.999976e-01 7.553071e-04 -2.035826e-03 -8.086759e-01 -7.854027e-04 9.998898e-01 -1.482298e-02 3.195559e-01 2.024406e-03 1.482454e-02 9.998881e-01 -7.997231e-01
+";
+
+    fn calib_storage(text: &str) -> MemStorage {
+        let mut storage = MemStorage::new();
+        storage.insert("calib/000000.txt", text);
+        storage
+    }
+
+    #[test]
+    fn parses_calib() {
+        let storage = calib_storage(CALIB);
+        let calib = KittiCalib::from_storage(&storage, Path::new("calib/000000.txt")).unwrap();
+
+        assert_eq!(calib.p1.m14, -3.797842e+02);
+        assert_eq!(calib.p2.m34, 4.981016e-03);
+        assert_eq!(calib.r0_rect.m12, 1.009263e-02);
+        assert_eq!(calib.velo_to_cam.m24, -6.127237e-02);
+        assert_eq!(calib.other_lines.len(), 1);
+        assert!(calib.other_lines[0].starts_with("Tr_imu_to_velo:"));
+    }
+
+    #[test]
+    fn write_round_trip() {
+        let storage = calib_storage(CALIB);
+        let calib = KittiCalib::from_storage(&storage, Path::new("calib/000000.txt")).unwrap();
+
+        let mut text = vec![];
+        calib.write(&mut text).unwrap();
+        let storage = calib_storage(std::str::from_utf8(&text).unwrap());
+        let written = KittiCalib::from_storage(&storage, Path::new("calib/000000.txt")).unwrap();
+
+        assert_eq!(written.p0, calib.p0);
+        assert_eq!(written.p1, calib.p1);
+        assert_eq!(written.p2, calib.p2);
+        assert_eq!(written.p3, calib.p3);
+        assert_eq!(written.r0_rect, calib.r0_rect);
+        assert_eq!(written.velo_to_cam, calib.velo_to_cam);
+        assert_eq!(written.other_lines, calib.other_lines);
     }
 }
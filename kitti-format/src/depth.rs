@@ -0,0 +1,174 @@
+use crate::{
+    calib::{KittiCalib, KittiCamera},
+    projection::ImageProjection,
+    storage::Storage,
+};
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder, ImageFormat};
+use nalgebra as na;
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+};
+
+/// A map in the 16-bit PNG format of the KITTI stereo and depth
+/// benchmarks. Each pixel holds a disparity in pixels or a depth in
+/// metres times 256, where 0 marks a pixel without a value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PixelMap {
+    pub width: u32,
+    pub height: u32,
+    // Raw pixel values, row by row
+    pub raw: Vec<u16>,
+}
+
+impl PixelMap {
+    /// Creates a map with no values.
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            raw: vec![0; (width * height) as usize],
+        }
+    }
+
+    /// Builds a sparse depth map by projecting points in the velodyne
+    /// frame onto the image. A pixel hit by several points keeps the
+    /// nearest depth.
+    pub fn from_points<'a, I>(
+        points: I,
+        projection: &ImageProjection,
+        width: u32,
+        height: u32,
+    ) -> Self
+    where
+        I: IntoIterator<Item = &'a na::Point3<f64>>,
+    {
+        let mut map = Self::new(width, height);
+        for point in points {
+            let Some((pixel, depth)) = projection.project(point) else {
+                continue;
+            };
+            if pixel.x < 0.0 || pixel.y < 0.0 {
+                continue;
+            }
+            let (x, y) = (pixel.x as u32, pixel.y as u32);
+            if x >= width || y >= height {
+                continue;
+            }
+            let raw = (depth * 256.0).round().clamp(1.0, u16::MAX as f64) as u16;
+            let prev = &mut map.raw[(y * width + x) as usize];
+            if *prev == 0 || raw < *prev {
+                *prev = raw;
+            }
+        }
+        map
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    pub fn from_storage(storage: &dyn Storage, path: &Path) -> io::Result<Self> {
+        Self::from_reader(storage.open(path)?)
+    }
+
+    pub fn from_reader<R: Read>(mut reader: R) -> io::Result<Self> {
+        let mut bytes = vec![];
+        reader.read_to_end(&mut bytes)?;
+        let image = image::load_from_memory_with_format(&bytes, ImageFormat::Png)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+            .into_luma16();
+        Ok(Self {
+            width: image.width(),
+            height: image.height(),
+            raw: image.into_raw(),
+        })
+    }
+
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_png(File::create(path)?)
+    }
+
+    /// Writes the map as a 16-bit greyscale PNG.
+    pub fn write_png<W: Write>(&self, writer: W) -> io::Result<()> {
+        let bytes: Vec<u8> = self.raw.iter().flat_map(|raw| raw.to_ne_bytes()).collect();
+        PngEncoder::new(writer)
+            .write_image(&bytes, self.width, self.height, ColorType::L16)
+            .map_err(io::Error::other)
+    }
+
+    /// The number of pixels with a value.
+    pub fn num_valid(&self) -> usize {
+        self.raw.iter().filter(|&&raw| raw != 0).count()
+    }
+
+    /// The value of a pixel, or `None` if it has none or lies outside
+    /// the map.
+    pub fn get(&self, x: u32, y: u32) -> Option<f64> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        match self.raw[(y * self.width + x) as usize] {
+            0 => None,
+            raw => Some(raw as f64 / 256.0),
+        }
+    }
+
+    /// Back-projects a disparity map of the left colour camera into the
+    /// velodyne frame, keeping every `stride`-th pixel in both directions.
+    pub fn disparity_to_points(&self, calib: &KittiCalib, stride: usize) -> Vec<na::Point3<f64>> {
+        let focal_baseline = calib.p2.m11 * calib.stereo_baseline();
+        self.back_project(calib, stride, |disparity| focal_baseline / disparity)
+    }
+
+    /// Back-projects a depth map of the left colour camera into the
+    /// velodyne frame, keeping every `stride`-th pixel in both directions.
+    pub fn depth_to_points(&self, calib: &KittiCalib, stride: usize) -> Vec<na::Point3<f64>> {
+        self.back_project(calib, stride, |depth| depth)
+    }
+
+    fn back_project(
+        &self,
+        calib: &KittiCalib,
+        stride: usize,
+        to_depth: impl Fn(f64) -> f64,
+    ) -> Vec<na::Point3<f64>> {
+        let projection = ImageProjection::new(calib, KittiCamera::Cam2);
+        (0..self.height)
+            .step_by(stride)
+            .flat_map(|y| (0..self.width).step_by(stride).map(move |x| (x, y)))
+            .filter_map(|(x, y)| {
+                let depth = to_depth(self.get(x, y)?);
+                projection.unproject(&na::Point2::new(x as f64, y as f64), depth)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemStorage;
+
+    #[test]
+    fn png_round_trip() {
+        let map = PixelMap {
+            width: 3,
+            height: 2,
+            raw: vec![0, 256, 1000, u16::MAX, 1, 0],
+        };
+        let mut png = vec![];
+        map.write_png(&mut png).unwrap();
+        let mut storage = MemStorage::new();
+        storage.insert("depth/000000.png", png);
+
+        let read = PixelMap::from_storage(&storage, Path::new("depth/000000.png")).unwrap();
+
+        assert_eq!(read, map);
+        assert_eq!(read.num_valid(), 4);
+        assert_eq!(read.get(1, 0), Some(1.0));
+        assert_eq!(read.get(0, 0), None);
+        assert_eq!(read.get(3, 0), None);
+    }
+}
@@ -1,6 +1,18 @@
 mod bbox;
+mod box_index;
 mod calib;
+mod depth;
 mod objects;
+mod planes;
+mod projection;
+mod semantic;
+mod storage;
 
+pub use box_index::*;
 pub use calib::*;
+pub use depth::*;
 pub use objects::*;
+pub use planes::*;
+pub use projection::*;
+pub use semantic::*;
+pub use storage::*;
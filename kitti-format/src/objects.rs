@@ -1,9 +1,16 @@
 use crate::{
     bbox::{BBox2D, BBox3D},
     calib::KittiCalib,
+    storage::Storage,
 };
 use nalgebra as na;
-use std::{f64::consts::FRAC_PI_2, fs::File, io, io::BufRead, path::PathBuf};
+use std::{
+    f64::consts::FRAC_PI_2,
+    fs::File,
+    io,
+    io::BufRead,
+    path::{Path, PathBuf},
+};
 use supervisely_format as sv;
 use sv::Vector3D;
 
@@ -23,9 +30,20 @@ impl KittiObject {
 }
 
 pub fn read_from_supervisely(ann_dir: &PathBuf) -> Vec<KittiObject> {
+    read_supervisely(&std::fs::read_to_string(ann_dir).unwrap())
+}
+
+pub fn read_supervisely_from_storage(
+    storage: &dyn Storage,
+    ann_path: &Path,
+) -> io::Result<Vec<KittiObject>> {
+    Ok(read_supervisely(&storage.read_to_string(ann_path)?))
+}
+
+pub fn read_supervisely(text: &str) -> Vec<KittiObject> {
     let sv::PointCloudAnnotation {
         figures, objects, ..
-    } = serde_json::from_str(&std::fs::read_to_string(ann_dir).unwrap()).unwrap();
+    } = serde_json::from_str(text).unwrap();
 
     let kitti_objects: Vec<KittiObject> = figures
         .iter()
@@ -94,7 +112,29 @@ pub fn read_ann_file_philly(
 ) -> Vec<KittiObject> {
     let file =
         File::open(ann_path.clone()).unwrap_or_else(|_| panic!("{:?} not exists!", ann_path));
-    let content_lines = io::BufReader::new(file).lines();
+    read_ann_philly(io::BufReader::new(file), _calib, exclude_classes)
+}
+
+pub fn read_ann_philly_from_storage(
+    storage: &dyn Storage,
+    ann_path: &Path,
+    calib: &KittiCalib,
+    exclude_classes: &[String],
+) -> io::Result<Vec<KittiObject>> {
+    let file = storage.open(ann_path)?;
+    Ok(read_ann_philly(
+        io::BufReader::new(file),
+        calib,
+        exclude_classes,
+    ))
+}
+
+pub fn read_ann_philly<R: BufRead>(
+    reader: R,
+    _calib: &KittiCalib,
+    exclude_classes: &[String],
+) -> Vec<KittiObject> {
+    let content_lines = reader.lines();
     let mut objects: Vec<KittiObject> = vec![];
     // let rect2velo = calib.get_transformation_from_rectified_camera_to_velodyne();
 
@@ -163,7 +203,25 @@ pub fn read_ann_file(
 ) -> Vec<KittiObject> {
     let file =
         File::open(ann_path.clone()).unwrap_or_else(|_| panic!("{:?} not exists!", ann_path));
-    let content_lines = io::BufReader::new(file).lines();
+    read_ann(io::BufReader::new(file), calib, exclude_classes)
+}
+
+pub fn read_ann_from_storage(
+    storage: &dyn Storage,
+    ann_path: &Path,
+    calib: &KittiCalib,
+    exclude_classes: &[String],
+) -> io::Result<Vec<KittiObject>> {
+    let file = storage.open(ann_path)?;
+    Ok(read_ann(io::BufReader::new(file), calib, exclude_classes))
+}
+
+pub fn read_ann<R: BufRead>(
+    reader: R,
+    calib: &KittiCalib,
+    exclude_classes: &[String],
+) -> Vec<KittiObject> {
+    let content_lines = reader.lines();
     let mut objects: Vec<KittiObject> = vec![];
     let rect2velo = calib.get_transformation_from_rectified_camera_to_velodyne();
     for line in content_lines {
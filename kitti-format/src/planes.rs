@@ -0,0 +1,157 @@
+use crate::{bbox::BBox3D, calib::KittiCalib, storage::Storage};
+use nalgebra as na;
+use std::{
+    fs::File,
+    io::{self, BufRead},
+    path::Path,
+};
+
+/// A road plane from the `planes` directory of the object benchmark,
+/// i.e. the points `p` with `normal.dot(p) + offset = 0`.
+///
+/// Planes are read in the rectified camera frame, where +y points down,
+/// so a normal such as `(0, -1, 0)` points up. [GroundPlane::to_velodyne]
+/// moves them to the velodyne frame with the normal pointing up whatever
+/// its sign in the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroundPlane {
+    // Unit normal of the plane
+    pub normal: na::Vector3<f64>,
+    pub offset: f64,
+}
+
+impl GroundPlane {
+    pub fn new(normal: na::Vector3<f64>, offset: f64) -> Self {
+        let norm = normal.norm();
+        Self {
+            normal: normal / norm,
+            offset: offset / norm,
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(plane_path: P) -> io::Result<Self> {
+        Self::from_reader(io::BufReader::new(File::open(plane_path)?))
+    }
+
+    pub fn from_storage(storage: &dyn Storage, plane_path: &Path) -> io::Result<Self> {
+        Self::from_reader(io::BufReader::new(storage.open(plane_path)?))
+    }
+
+    /// Parses a plane file. The coefficients are on the first line with
+    /// four numbers, after the `# Plane`, `Width` and `Height` lines.
+    pub fn from_reader<R: BufRead>(reader: R) -> io::Result<Self> {
+        for line in reader.lines() {
+            let line = line?;
+            let Ok(vals) = line
+                .split_whitespace()
+                .map(|s| s.parse::<f64>())
+                .collect::<Result<Vec<_>, _>>()
+            else {
+                continue;
+            };
+            if let [a, b, c, d] = vals[..] {
+                return Ok(Self::new(na::Vector3::new(a, b, c), d));
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "missing plane coefficients",
+        ))
+    }
+
+    /// Moves a plane from the rectified camera frame to the velodyne
+    /// frame, flipping it so that the normal points up.
+    pub fn to_velodyne(&self, calib: &KittiCalib) -> Self {
+        let rect_to_velo = calib.get_transformation_from_rectified_camera_to_velodyne();
+        self.transform(&rect_to_velo).upward()
+    }
+
+    pub fn transform(&self, transform: &na::Isometry3<f64>) -> Self {
+        let normal = transform.rotation * self.normal;
+        let point_on_plane = transform * na::Point3::from(-self.offset * self.normal);
+        Self {
+            normal,
+            offset: -normal.dot(&point_on_plane.coords),
+        }
+    }
+
+    fn upward(self) -> Self {
+        if self.normal.z < 0.0 {
+            Self {
+                normal: -self.normal,
+                offset: -self.offset,
+            }
+        } else {
+            self
+        }
+    }
+
+    /// The signed distance of a point from the plane, positive on the
+    /// side the normal points to.
+    pub fn height_of(&self, point: &na::Point3<f64>) -> f64 {
+        self.normal.dot(&point.coords) + self.offset
+    }
+
+    /// The height of the lowest corner of a box. It is close to zero
+    /// for objects standing on the road.
+    pub fn height_of_box(&self, bbox: &BBox3D) -> f64 {
+        bbox.vertices()
+            .iter()
+            .map(|vertex| self.height_of(vertex))
+            .fold(f64::INFINITY, f64::min)
+    }
+
+    /// Finds the point of the plane above or below `(x, y)`, or `None`
+    /// for a vertical plane.
+    pub fn point_at(&self, x: f64, y: f64) -> Option<na::Point3<f64>> {
+        if self.normal.z.abs() < 1e-6 {
+            return None;
+        }
+        let z = -(self.normal.x * x + self.normal.y * y + self.offset) / self.normal.z;
+        Some(na::Point3::new(x, y, z))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemStorage;
+
+    // A rectified camera frame with x right, y down and z forward, 1.65 m
+    // above the road like the KITTI rig.
+    const CALIB: &str = "\
+R0_rect: 1 0 0 0 1 0 0 0 1
+Tr_velo_to_cam: 0 -1 0 0 0 0 -1 0 1 0 0 0
+";
+    const PLANE: &str =
+        "# Plane\nWidth 4\nHeight 1\n0.000000e+00 -1.000000e+00 0.000000e+00 1.650000e+00\n";
+
+    fn load_plane() -> GroundPlane {
+        let mut storage = MemStorage::new();
+        storage.insert("planes/000000.txt", PLANE);
+        GroundPlane::from_storage(&storage, Path::new("planes/000000.txt")).unwrap()
+    }
+
+    #[test]
+    fn parses_plane() {
+        let plane = load_plane();
+        assert_eq!(plane.normal, na::Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(plane.offset, 1.65);
+    }
+
+    #[test]
+    fn moves_plane_to_velodyne() {
+        let calib = KittiCalib::from_reader(CALIB.as_bytes());
+        let plane = load_plane().to_velodyne(&calib);
+
+        assert!((plane.normal - na::Vector3::z()).norm() < 1e-9);
+        assert!((plane.height_of(&na::Point3::origin()) - 1.65).abs() < 1e-9);
+        assert!(plane.height_of(&na::Point3::new(5.0, 2.0, -1.65)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rejects_missing_coefficients() {
+        let err = GroundPlane::from_reader("# Plane\nWidth 4\nHeight 1\n".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,97 @@
+use crate::calib::{KittiCalib, KittiCamera};
+use nalgebra as na;
+use rayon::prelude::*;
+
+/// Projects points in the velodyne frame onto the image of a camera.
+#[derive(Debug, Clone)]
+pub struct ImageProjection {
+    // Coordinate transformation from Lidar to camera image (homogeneous)
+    velo_to_image: na::Matrix3x4<f64>,
+    // Inverse of the left 3x3 block of velo_to_image
+    image_to_velo: Option<na::Matrix3<f64>>,
+}
+
+impl ImageProjection {
+    pub fn new(calib: &KittiCalib, camera: KittiCamera) -> Self {
+        let velo_to_rect = calib
+            .get_transformation_from_rectified_camera_to_velodyne()
+            .inverse();
+        Self::from_matrix(calib.projection(camera) * velo_to_rect.to_homogeneous())
+    }
+
+    pub fn from_matrix(velo_to_image: na::Matrix3x4<f64>) -> Self {
+        Self {
+            velo_to_image,
+            image_to_velo: velo_to_image.fixed_slice::<3, 3>(0, 0).try_inverse(),
+        }
+    }
+
+    pub fn matrix(&self) -> &na::Matrix3x4<f64> {
+        &self.velo_to_image
+    }
+
+    /// Projects a point to its pixel coordinates and its depth along the
+    /// optical axis. Points behind the camera give `None`.
+    pub fn project(&self, point: &na::Point3<f64>) -> Option<(na::Point2<f64>, f64)> {
+        let image_point = self.velo_to_image * point.to_homogeneous();
+        let depth = image_point.z;
+        if depth <= 0.0 {
+            return None;
+        }
+        let pixel = na::Point2::new(image_point.x / depth, image_point.y / depth);
+        Some((pixel, depth))
+    }
+
+    /// Whether a point projects into an image of the given size with a
+    /// positive depth, i.e. lies in the field of view of the camera.
+    pub fn in_image(&self, point: &na::Point3<f64>, width: u32, height: u32) -> bool {
+        self.project(point).is_some_and(|(pixel, _)| {
+            (0.0..width as f64).contains(&pixel.x) && (0.0..height as f64).contains(&pixel.y)
+        })
+    }
+
+    /// Tells for each `[x, y, z, intensity]` record of a scan whether it
+    /// lies in the field of view of the camera. Keeping the points and
+    /// every per-point channel where the mask is `true` crops the scan
+    /// as most detectors trained on KITTI do with their input.
+    pub fn image_mask(&self, points: &[[f32; 4]], width: u32, height: u32) -> Vec<bool> {
+        points
+            .par_iter()
+            .map(|&[x, y, z, _]| self.in_image(&na::Point3::new(x, y, z).cast(), width, height))
+            .collect()
+    }
+
+    /// Finds the point that projects to a pixel at a depth, undoing
+    /// [ImageProjection::project]. Gives `None` for a degenerate
+    /// projection.
+    pub fn unproject(&self, pixel: &na::Point2<f64>, depth: f64) -> Option<na::Point3<f64>> {
+        let image_to_velo = self.image_to_velo.as_ref()?;
+        let image_point = na::Vector3::new(pixel.x * depth, pixel.y * depth, depth);
+        Some(na::Point3::from(
+            image_to_velo * (image_point - self.velo_to_image.column(3)),
+        ))
+    }
+
+    /// The optical centre of the camera.
+    pub fn camera_center(&self) -> Option<na::Point3<f64>> {
+        self.unproject(&na::Point2::origin(), 0.0)
+    }
+
+    /// The corners of an image of the given size at a depth, clockwise
+    /// from the top left. With the camera centre they span the frustum
+    /// of the camera.
+    pub fn frustum_corners(
+        &self,
+        width: u32,
+        height: u32,
+        depth: f64,
+    ) -> Option<[na::Point3<f64>; 4]> {
+        let (width, height) = (width as f64, height as f64);
+        Some([
+            self.unproject(&na::Point2::new(0.0, 0.0), depth)?,
+            self.unproject(&na::Point2::new(width, 0.0), depth)?,
+            self.unproject(&na::Point2::new(width, height), depth)?,
+            self.unproject(&na::Point2::new(0.0, height), depth)?,
+        ])
+    }
+}
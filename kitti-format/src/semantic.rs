@@ -0,0 +1,166 @@
+use crate::storage::Storage;
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+};
+
+/// The label of one point in a SemanticKITTI `.label` file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SemanticLabel {
+    // Semantic class id, e.g. 10 for car
+    pub semantic: u16,
+    // Instance id, unique among the objects of a frame
+    pub instance: u16,
+}
+
+impl SemanticLabel {
+    pub fn from_raw(raw: u32) -> Self {
+        Self {
+            semantic: (raw & 0xffff) as u16,
+            instance: (raw >> 16) as u16,
+        }
+    }
+
+    pub fn to_raw(self) -> u32 {
+        (self.instance as u32) << 16 | self.semantic as u32
+    }
+
+    /// The class name, e.g. `car`.
+    pub fn class_name(&self) -> Option<&'static str> {
+        semantic_class(self.semantic).map(|(name, _)| name)
+    }
+
+    /// The colour of the class in the SemanticKITTI colour map, black
+    /// for unknown classes.
+    pub fn color(&self) -> [u8; 3] {
+        semantic_class(self.semantic).map_or([0, 0, 0], |(_, color)| color)
+    }
+}
+
+/// The classes of SemanticKITTI with their RGB colours, taken from the
+/// `labels` and `color_map` of `semantic-kitti.yaml`.
+pub const SEMANTIC_CLASSES: &[(u16, &str, [u8; 3])] = &[
+    (0, "unlabeled", [0, 0, 0]),
+    (1, "outlier", [255, 0, 0]),
+    (10, "car", [100, 150, 245]),
+    (11, "bicycle", [100, 230, 245]),
+    (13, "bus", [100, 80, 250]),
+    (15, "motorcycle", [30, 60, 150]),
+    (16, "on-rails", [0, 0, 255]),
+    (18, "truck", [80, 30, 180]),
+    (20, "other-vehicle", [0, 0, 255]),
+    (30, "person", [255, 30, 30]),
+    (31, "bicyclist", [255, 40, 200]),
+    (32, "motorcyclist", [150, 30, 90]),
+    (40, "road", [255, 0, 255]),
+    (44, "parking", [255, 150, 255]),
+    (48, "sidewalk", [75, 0, 75]),
+    (49, "other-ground", [175, 0, 75]),
+    (50, "building", [255, 200, 0]),
+    (51, "fence", [255, 120, 50]),
+    (52, "other-structure", [255, 150, 0]),
+    (60, "lane-marking", [150, 255, 170]),
+    (70, "vegetation", [0, 175, 0]),
+    (71, "trunk", [135, 60, 0]),
+    (72, "terrain", [150, 240, 80]),
+    (80, "pole", [255, 240, 150]),
+    (81, "traffic-sign", [255, 0, 0]),
+    (99, "other-object", [50, 255, 255]),
+    (252, "moving-car", [100, 150, 245]),
+    (253, "moving-bicyclist", [255, 40, 200]),
+    (254, "moving-person", [255, 30, 30]),
+    (255, "moving-motorcyclist", [150, 30, 90]),
+    (256, "moving-on-rails", [0, 0, 255]),
+    (257, "moving-bus", [100, 80, 250]),
+    (258, "moving-truck", [80, 30, 180]),
+    (259, "moving-other-vehicle", [0, 0, 255]),
+];
+
+/// Looks up the name and colour of a SemanticKITTI class id.
+pub fn semantic_class(semantic: u16) -> Option<(&'static str, [u8; 3])> {
+    SEMANTIC_CLASSES
+        .iter()
+        .find(|(id, _, _)| *id == semantic)
+        .map(|&(_, name, color)| (name, color))
+}
+
+/// Reads a SemanticKITTI `.label` file, which stores one little-endian
+/// `u32` per point with the semantic class in the lower 16 bits and the
+/// instance id in the upper 16 bits.
+pub fn read_semantic_labels<R: Read>(mut reader: R) -> io::Result<Vec<SemanticLabel>> {
+    let mut bytes = vec![];
+    reader.read_to_end(&mut bytes)?;
+    if !bytes.len().is_multiple_of(4) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "expect a multiple of 4 bytes, but found {} bytes",
+                bytes.len()
+            ),
+        ));
+    }
+    let labels = bytes
+        .chunks_exact(4)
+        .map(|chunk| SemanticLabel::from_raw(u32::from_le_bytes(chunk.try_into().unwrap())))
+        .collect();
+    Ok(labels)
+}
+
+pub fn read_semantic_labels_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<SemanticLabel>> {
+    read_semantic_labels(File::open(path)?)
+}
+
+pub fn read_semantic_labels_from_storage(
+    storage: &dyn Storage,
+    path: &Path,
+) -> io::Result<Vec<SemanticLabel>> {
+    read_semantic_labels(storage.open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemStorage;
+
+    #[test]
+    fn reads_labels() {
+        let raw: [u32; 3] = [0, 10, 7 << 16 | 30];
+        let bytes: Vec<u8> = raw.iter().flat_map(|raw| raw.to_le_bytes()).collect();
+        let mut storage = MemStorage::new();
+        storage.insert("labels/000000.label", bytes);
+
+        let labels =
+            read_semantic_labels_from_storage(&storage, Path::new("labels/000000.label")).unwrap();
+
+        assert_eq!(
+            labels,
+            [
+                SemanticLabel {
+                    semantic: 0,
+                    instance: 0
+                },
+                SemanticLabel {
+                    semantic: 10,
+                    instance: 0
+                },
+                SemanticLabel {
+                    semantic: 30,
+                    instance: 7
+                },
+            ]
+        );
+        assert_eq!(labels[1].class_name(), Some("car"));
+        assert_eq!(labels[2].to_raw(), raw[2]);
+    }
+
+    #[test]
+    fn rejects_partial_labels() {
+        let mut storage = MemStorage::new();
+        storage.insert("labels/000000.label", vec![0; 6]);
+
+        let err = read_semantic_labels_from_storage(&storage, Path::new("labels/000000.label"))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,562 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use zip::ZipArchive;
+
+/// Read-only access to dataset files addressed by relative paths such
+/// as `velodyne/000000.bin`.
+pub trait Storage: fmt::Display + Send + Sync {
+    /// Lists the files directly under `dir`, sorted. Fails with
+    /// [io::ErrorKind::NotFound] if the directory does not exist or
+    /// holds no files, as archives have no notion of empty directories.
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Opens a file for sequential reading.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send + '_>>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Returns the path on the local file system if the file is stored
+    /// there as is, which allows it to be memory-mapped.
+    fn local_path(&self, _path: &Path) -> Option<PathBuf> {
+        None
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.open(path)?.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        let mut text = String::new();
+        self.open(path)?.read_to_string(&mut text)?;
+        Ok(text)
+    }
+}
+
+/// A KITTI dataset root composed of one or more storages, e.g. an
+/// unpacked directory plus the official zip archives
+/// (`data_object_velodyne.zip`, ...). The first storage containing a
+/// requested file wins.
+pub struct KittiSource {
+    layers: Vec<Box<dyn Storage>>,
+}
+
+impl KittiSource {
+    /// Opens each root as a directory, a `.zip` archive or a `.tar`
+    /// archive. Archive entries under `<split>/` are indexed without
+    /// that prefix, so `training/velodyne/000000.bin` becomes
+    /// `velodyne/000000.bin`.
+    pub fn new<P: AsRef<Path>>(roots: &[P], split: &str) -> io::Result<Self> {
+        let layers = roots
+            .iter()
+            .map(|root| -> io::Result<Box<dyn Storage>> {
+                let root = root.as_ref();
+                let ext = root.extension().and_then(|ext| ext.to_str());
+
+                if root.is_dir() {
+                    Ok(Box::new(LocalDir::new(root)))
+                } else if ext == Some("zip") {
+                    Ok(Box::new(ZipStorage::open(root, split)?))
+                } else if ext == Some("tar") {
+                    Ok(Box::new(TarStorage::open(root, split)?))
+                } else {
+                    Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!(
+                            "{} is neither a directory nor a zip or tar file",
+                            root.display()
+                        ),
+                    ))
+                }
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self { layers })
+    }
+
+    pub fn from_storages(layers: Vec<Box<dyn Storage>>) -> Self {
+        Self { layers }
+    }
+
+    fn find(&self, path: &Path) -> io::Result<&dyn Storage> {
+        self.layers
+            .iter()
+            .find(|layer| layer.exists(path))
+            .map(|layer| layer.as_ref())
+            .ok_or_else(|| not_found(path, self))
+    }
+}
+
+impl Storage for KittiSource {
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut found = false;
+        let mut paths = BTreeSet::new();
+
+        for layer in &self.layers {
+            match layer.list(dir) {
+                Ok(entries) => {
+                    found = true;
+                    paths.extend(entries);
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        if !found {
+            return Err(not_found(dir, self));
+        }
+        Ok(paths.into_iter().collect())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send + '_>> {
+        self.find(path)?.open(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.layers.iter().any(|layer| layer.exists(path))
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        self.find(path).ok()?.local_path(path)
+    }
+}
+
+impl fmt::Display for KittiSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (idx, layer) in self.layers.iter().enumerate() {
+            if idx > 0 {
+                write!(f, " + ")?;
+            }
+            write!(f, "{layer}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Files in a directory on the local file system.
+pub struct LocalDir {
+    root: PathBuf,
+}
+
+impl LocalDir {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+}
+
+impl Storage for LocalDir {
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for entry in self.root.join(dir).read_dir()? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                paths.push(dir.join(entry.file_name()));
+            }
+        }
+        if paths.is_empty() {
+            return Err(not_found(dir, self));
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send + '_>> {
+        Ok(Box::new(File::open(self.root.join(path))?))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.root.join(path).is_file()
+    }
+
+    fn local_path(&self, path: &Path) -> Option<PathBuf> {
+        let path = self.root.join(path);
+        path.is_file().then_some(path)
+    }
+}
+
+impl fmt::Display for LocalDir {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.root.display())
+    }
+}
+
+/// Files in a zip archive. The central directory is indexed once on
+/// open and entries are decompressed into memory on access.
+pub struct ZipStorage {
+    path: PathBuf,
+    archive: Mutex<ZipArchive<File>>,
+    /// Maps split-relative paths to entry names in the archive.
+    index: HashMap<String, String>,
+}
+
+impl ZipStorage {
+    pub fn open<P: AsRef<Path>>(path: P, split: &str) -> io::Result<Self> {
+        let path = path.as_ref();
+        let archive = ZipArchive::new(File::open(path)?)?;
+        let split_prefix = format!("{split}/");
+        let index = archive
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(|name| {
+                let key = name.strip_prefix(&split_prefix).unwrap_or(name);
+                (key.to_string(), name.to_string())
+            })
+            .collect();
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            archive: Mutex::new(archive),
+            index,
+        })
+    }
+
+    fn read_entry(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let name = self
+            .index
+            .get(&entry_key(path))
+            .ok_or_else(|| not_found(path, self))?;
+        let mut archive = self.archive.lock().unwrap();
+        let mut entry = archive.by_name(name)?;
+        let mut buf = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+impl Storage for ZipStorage {
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        list_keys(self.index.keys(), dir).ok_or_else(|| not_found(dir, self))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send + '_>> {
+        Ok(Box::new(Cursor::new(self.read_entry(path)?)))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.index.contains_key(&entry_key(path))
+    }
+}
+
+impl fmt::Display for ZipStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())
+    }
+}
+
+/// Files in an uncompressed tar archive. Entry offsets are indexed once
+/// on open so that reads seek straight to the data.
+pub struct TarStorage {
+    path: PathBuf,
+    /// Maps split-relative paths to the data offset and size.
+    index: HashMap<String, (u64, u64)>,
+}
+
+impl TarStorage {
+    pub fn open<P: AsRef<Path>>(path: P, split: &str) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut archive = tar::Archive::new(File::open(path)?);
+        let split_prefix = format!("{split}/");
+        let mut index = HashMap::new();
+
+        for entry in archive.entries_with_seek()? {
+            let entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry_key(&entry.path()?);
+            let name = name.strip_prefix("./").unwrap_or(&name);
+            let key = name.strip_prefix(&split_prefix).unwrap_or(name);
+            index.insert(key.to_string(), (entry.raw_file_position(), entry.size()));
+        }
+
+        Ok(Self {
+            path: path.to_path_buf(),
+            index,
+        })
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<(u64, u64)> {
+        self.index
+            .get(&entry_key(path))
+            .copied()
+            .ok_or_else(|| not_found(path, self))
+    }
+}
+
+impl Storage for TarStorage {
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        list_keys(self.index.keys(), dir).ok_or_else(|| not_found(dir, self))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send + '_>> {
+        let (offset, size) = self.entry(path)?;
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Box::new(file.take(size)))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.index.contains_key(&entry_key(path))
+    }
+}
+
+impl fmt::Display for TarStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.path.display())
+    }
+}
+
+/// Files held in memory, mainly for fixtures.
+#[derive(Default)]
+pub struct MemStorage {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P, data: impl Into<Vec<u8>>) {
+        self.files.insert(entry_key(path.as_ref()), data.into());
+    }
+
+    fn entry(&self, path: &Path) -> io::Result<&[u8]> {
+        self.files
+            .get(&entry_key(path))
+            .map(|data| data.as_slice())
+            .ok_or_else(|| not_found(path, self))
+    }
+}
+
+impl Storage for MemStorage {
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        list_keys(self.files.keys(), dir).ok_or_else(|| not_found(dir, self))
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send + '_>> {
+        Ok(Box::new(self.entry(path)?))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&entry_key(path))
+    }
+}
+
+impl fmt::Display for MemStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<memory>")
+    }
+}
+
+/// Converts a relative path into the `/`-separated form used as the
+/// archive index key.
+fn entry_key(path: &Path) -> String {
+    path.iter()
+        .map(|comp| comp.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Picks the keys directly under `dir`. Returns `None` if there are none.
+fn list_keys<'a>(keys: impl Iterator<Item = &'a String>, dir: &Path) -> Option<Vec<PathBuf>> {
+    let prefix = entry_key(dir);
+    let mut paths: Vec<_> = keys
+        .filter_map(|key| {
+            let name = if prefix.is_empty() {
+                key.as_str()
+            } else {
+                key.strip_prefix(&prefix)?.strip_prefix('/')?
+            };
+            (!name.contains('/')).then(|| dir.join(name))
+        })
+        .collect();
+
+    if paths.is_empty() {
+        return None;
+    }
+    paths.sort();
+    Some(paths)
+}
+
+fn not_found(path: &Path, storage: &dyn fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("{} not found in {storage}", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, io::Write, process};
+
+    const FILES: &[(&str, &[u8])] = &[
+        ("velodyne/000000.bin", b"scan 0"),
+        ("velodyne/000001.bin", b"scan 1"),
+        ("calib/000000.txt", b"P0: 1 0 0 0"),
+    ];
+
+    /// A scratch directory that is removed on drop.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("kitti-format-{name}-{}", process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Writes the files plus an empty `image_2` directory.
+    fn write_dir(root: &Path, files: &[(&str, &[u8])]) {
+        for (name, data) in files {
+            let path = root.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, data).unwrap();
+        }
+        fs::create_dir_all(root.join("image_2")).unwrap();
+    }
+
+    fn write_zip(path: &Path, prefix: &str, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        zip.add_directory(format!("{prefix}image_2/"), Default::default())
+            .unwrap();
+        for (name, data) in files {
+            zip.start_file(format!("{prefix}{name}"), Default::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tar(path: &Path, prefix: &str, files: &[(&str, &[u8])]) {
+        let mut tar = tar::Builder::new(File::create(path).unwrap());
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_size(0);
+        header.set_mode(0o755);
+        tar.append_data(&mut header, format!("{prefix}image_2/"), io::empty())
+            .unwrap();
+        for (name, data) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            tar.append_data(&mut header, format!("{prefix}{name}"), *data)
+                .unwrap();
+        }
+        tar.finish().unwrap();
+    }
+
+    /// Checks that the storage holds exactly [FILES].
+    fn check(storage: &dyn Storage) {
+        assert_eq!(
+            storage.list(Path::new("velodyne")).unwrap(),
+            [
+                PathBuf::from("velodyne/000000.bin"),
+                PathBuf::from("velodyne/000001.bin")
+            ]
+        );
+        assert_eq!(
+            storage.list(Path::new("calib")).unwrap(),
+            [PathBuf::from("calib/000000.txt")]
+        );
+        for (name, data) in FILES {
+            assert!(storage.exists(Path::new(name)), "{name} in {storage}");
+            assert_eq!(storage.read(Path::new(name)).unwrap(), *data);
+        }
+
+        for dir in ["image_2", "label_2"] {
+            let err = storage.list(Path::new(dir)).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::NotFound, "{dir} in {storage}");
+        }
+        let missing = Path::new("velodyne/000002.bin");
+        assert!(!storage.exists(missing));
+        assert!(matches!(
+            storage.open(missing),
+            Err(err) if err.kind() == io::ErrorKind::NotFound
+        ));
+    }
+
+    #[test]
+    fn local_dir() {
+        let dir = TempDir::new("local-dir");
+        write_dir(&dir.0, FILES);
+        check(&LocalDir::new(&dir.0));
+    }
+
+    #[test]
+    fn zip_storage() {
+        let dir = TempDir::new("zip-storage");
+        for prefix in ["", "training/"] {
+            let path = dir.0.join("data.zip");
+            write_zip(&path, prefix, FILES);
+            check(&ZipStorage::open(&path, "training").unwrap());
+        }
+    }
+
+    #[test]
+    fn tar_storage() {
+        let dir = TempDir::new("tar-storage");
+        for prefix in ["", "./", "training/"] {
+            let path = dir.0.join("data.tar");
+            write_tar(&path, prefix, FILES);
+            check(&TarStorage::open(&path, "training").unwrap());
+        }
+    }
+
+    #[test]
+    fn kitti_source_merges_layers() {
+        let dir = TempDir::new("kitti-source");
+        let root = dir.0.join("root");
+        let zip = dir.0.join("data_object_velodyne.zip");
+        let tar = dir.0.join("data_object_calib.tar");
+        write_dir(&root, &FILES[..1]);
+        write_zip(&zip, "training/", &FILES[1..2]);
+        write_tar(&tar, "training/", &FILES[2..]);
+
+        let source = KittiSource::new(&[root, zip, tar], "training").unwrap();
+        check(&source);
+
+        let err = KittiSource::new(&[dir.0.join("missing.bin")], "training")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn first_layer_wins() {
+        let mut front = MemStorage::new();
+        front.insert("velodyne/000000.bin", "front");
+        let mut back = MemStorage::new();
+        back.insert("velodyne/000000.bin", "back");
+        back.insert("velodyne/000001.bin", "back");
+
+        let source = KittiSource::from_storages(vec![Box::new(front), Box::new(back)]);
+        assert_eq!(
+            source.read(Path::new("velodyne/000000.bin")).unwrap(),
+            b"front"
+        );
+        assert_eq!(source.list(Path::new("velodyne")).unwrap().len(), 2);
+    }
+}
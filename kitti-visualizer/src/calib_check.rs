@@ -0,0 +1,132 @@
+use crate::{
+    calib_score::{alignment_score, depth_edges, EdgeMap},
+    compress::write_atomic,
+    utils::{
+        list_image_indices, load_camera_frame, load_frame_cloud, CloudConfig, ImageDatasetOpts,
+    },
+};
+use anyhow::{Context, Result};
+use clap::Args;
+use kitti_format::{ImageProjection, KittiCamera, KittiSource};
+use rayon::prelude::*;
+use std::{fmt::Write, path::PathBuf};
+
+#[derive(Debug, Clone, Args)]
+pub struct CalibCheckOpts {
+    #[clap(flatten)]
+    pub dataset: ImageDatasetOpts,
+    /// The CSV file to write the score of every frame to.
+    pub report: PathBuf,
+    /// Frames whose score differs from the median by more than this
+    /// fraction of the median are flagged.
+    #[clap(long, default_value = "0.3")]
+    pub max_deviation: f64,
+}
+
+/// Scores how well the scan of every frame lines up with its `image_2`
+/// picture and flags the frames that stand out from the rest, which
+/// usually have a wrong calibration or a badly synchronised scan.
+/// Frames that cannot be loaded are listed with their error.
+pub fn check_calibration(opts: &CalibCheckOpts) -> Result<()> {
+    let CalibCheckOpts {
+        dataset:
+            ImageDatasetOpts {
+                ref input,
+                ref split,
+                cloud_format,
+            },
+        ref report,
+        max_deviation,
+    } = *opts;
+
+    let source = KittiSource::new(input, split)?;
+    let indices = list_image_indices(&source)?;
+    let cloud_config = CloudConfig::scan_only(cloud_format);
+
+    // A frame that fails is listed in the report instead of stopping
+    // the run
+    let results: Vec<Result<Option<f64>>> = indices
+        .par_iter()
+        .map(|&index| -> Result<_> {
+            let camera = load_camera_frame(index, &source, &cloud_config)?
+                .with_context(|| format!("no calibration for frame {index}"))?;
+            let cloud = load_frame_cloud(index, &source, &cloud_config)?;
+            let projection = ImageProjection::new(&camera.calib, KittiCamera::Cam2);
+            let edge_map = EdgeMap::new(&camera.image);
+            Ok(alignment_score(
+                &edge_map,
+                &depth_edges(cloud.points()),
+                &projection,
+            ))
+        })
+        .collect();
+
+    let mut sorted: Vec<f64> = results
+        .iter()
+        .filter_map(|result| *result.as_ref().ok()?)
+        .collect();
+    sorted.sort_by(f64::total_cmp);
+    let median = match sorted.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(sorted[len / 2]),
+        len => Some((sorted[len / 2 - 1] + sorted[len / 2]) / 2.0),
+    };
+
+    // Frames without a score have no depth edge in the image, which is
+    // as suspicious as a low score.
+    let mut text = "index,score,deviation,flagged,error\n".to_string();
+    let mut flagged = vec![];
+    let mut failed = vec![];
+    let cell = |value: Option<f64>| value.map_or(String::new(), |value| format!("{value:.4}"));
+    for (&index, result) in indices.iter().zip(&results) {
+        let score = match result {
+            Ok(score) => *score,
+            Err(err) => {
+                failed.push(index);
+                let message = format!("{err:#}").replace('"', "\"\"");
+                writeln!(text, "{index:0>6},,,false,\"{message}\"")?;
+                continue;
+            }
+        };
+        let deviation = score.zip(median).map(|(score, median)| {
+            if median > 0.0 {
+                (score - median) / median
+            } else {
+                0.0
+            }
+        });
+        let is_flagged = deviation.is_none_or(|deviation| deviation.abs() > max_deviation);
+        if is_flagged {
+            flagged.push(index);
+        }
+        writeln!(
+            text,
+            "{index:0>6},{},{},{is_flagged},",
+            cell(score),
+            cell(deviation)
+        )?;
+    }
+    write_atomic(report, text.as_bytes())?;
+
+    match median {
+        Some(median) => eprintln!(
+            "scored {} frames with a median of {median:.4}, {} flagged, {} failed",
+            indices.len() - failed.len(),
+            flagged.len(),
+            failed.len()
+        ),
+        None => eprintln!("no frame of {} could be scored", indices.len()),
+    }
+    let list = |indices: &[i32]| -> String {
+        let names: Vec<String> = indices.iter().map(|index| format!("{index:0>6}")).collect();
+        names.join(" ")
+    };
+    if !flagged.is_empty() {
+        eprintln!("flagged: {}", list(&flagged));
+    }
+    if !failed.is_empty() {
+        eprintln!("failed: {}", list(&failed));
+    }
+    eprintln!("wrote the report to {}", report.display());
+    Ok(())
+}
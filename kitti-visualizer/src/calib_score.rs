@@ -0,0 +1,120 @@
+use image::RgbImage;
+use kitti_format::ImageProjection;
+use nalgebra as na;
+use rayon::prelude::*;
+
+/// How much of the edge strength carries over to the next pixel when
+/// edges are spread out.
+const EDGE_DECAY: f32 = 0.9;
+/// Points less than this much closer than their neighbours on the scan
+/// line are not depth edges.
+const MIN_DEPTH_JUMP: f64 = 0.5;
+
+/// The edges of an image, spread out with a decaying maximum so that the
+/// score grows smoothly as the depth edges move toward the image edges.
+#[derive(Debug, Clone)]
+pub struct EdgeMap {
+    width: u32,
+    height: u32,
+    // Edge strength between 0 and 1, row by row
+    values: Vec<f32>,
+}
+
+impl EdgeMap {
+    pub fn new(image: &RgbImage) -> Self {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let grey: Vec<f32> = image
+            .pixels()
+            .map(|pixel| pixel.0.iter().map(|&c| c as f32).sum::<f32>() / (3.0 * 255.0))
+            .collect();
+
+        // The largest difference to the 8 neighbours of each pixel
+        let mut values: Vec<f32> = (0..width * height)
+            .into_par_iter()
+            .map(|idx| {
+                let (x, y) = (idx % width, idx / width);
+                let mut edge: f32 = 0.0;
+                for ny in y.saturating_sub(1)..(y + 2).min(height) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(width) {
+                        edge = edge.max((grey[idx] - grey[ny * width + nx]).abs());
+                    }
+                }
+                edge
+            })
+            .collect();
+
+        let spread = |values: &mut [f32], stride: usize, len: usize| {
+            for idx in 1..len {
+                let prev = values[(idx - 1) * stride] * EDGE_DECAY;
+                values[idx * stride] = values[idx * stride].max(prev);
+            }
+            for idx in (0..len - 1).rev() {
+                let next = values[(idx + 1) * stride] * EDGE_DECAY;
+                values[idx * stride] = values[idx * stride].max(next);
+            }
+        };
+        if width > 0 && height > 0 {
+            for y in 0..height {
+                spread(&mut values[y * width..], 1, width);
+            }
+            for x in 0..width {
+                spread(&mut values[x..], width, height);
+            }
+        }
+
+        Self {
+            width: image.width(),
+            height: image.height(),
+            values,
+        }
+    }
+
+    /// The edge strength at a pixel, or `None` outside the image.
+    pub fn get(&self, pixel: &na::Point2<f64>) -> Option<f32> {
+        if pixel.x < 0.0 || pixel.y < 0.0 {
+            return None;
+        }
+        let (x, y) = (pixel.x as u32, pixel.y as u32);
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.values[(y * self.width + x) as usize])
+    }
+}
+
+/// Finds the points of a scan that are closer than a neighbour on the
+/// scan line, weighted by the size of the jump. Scans store the points
+/// of a laser ring next to each other.
+pub fn depth_edges(points: &[[f32; 4]]) -> Vec<(na::Point3<f64>, f64)> {
+    let ranges: Vec<f64> = points
+        .iter()
+        .map(|&[x, y, z, _]| na::Vector3::new(x, y, z).cast::<f64>().norm())
+        .collect();
+    (1..ranges.len().saturating_sub(1))
+        .filter_map(|idx| {
+            let jump = (ranges[idx - 1] - ranges[idx]).max(ranges[idx + 1] - ranges[idx]);
+            let [x, y, z, _] = points[idx];
+            (jump >= MIN_DEPTH_JUMP).then(|| (na::Point3::new(x, y, z).cast(), jump.sqrt()))
+        })
+        .collect()
+}
+
+/// Scores how well the depth edges of a scan line up with the edges of
+/// the image, from 0 to 1. Gives `None` if no depth edge projects into
+/// the image.
+pub fn alignment_score(
+    edge_map: &EdgeMap,
+    depth_edges: &[(na::Point3<f64>, f64)],
+    projection: &ImageProjection,
+) -> Option<f64> {
+    let (score, total_weight) = depth_edges
+        .iter()
+        .filter_map(|(point, weight)| {
+            let (pixel, _) = projection.project(point)?;
+            Some((edge_map.get(&pixel)? as f64 * weight, *weight))
+        })
+        .fold((0.0, 0.0), |(score, total), (value, weight)| {
+            (score + value, total + weight)
+        });
+    (total_weight > 0.0).then(|| score / total_weight)
+}
@@ -0,0 +1,207 @@
+use crate::read_pcd::check_scan_len;
+use anyhow::{ensure, Context, Result};
+use clap::{Args, ValueEnum};
+use rayon::prelude::*;
+use std::{
+    fs,
+    io::{self, prelude::*},
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum Codec {
+    Zstd,
+    Lz4,
+    Gzip,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 3] = [Codec::Zstd, Codec::Lz4, Codec::Gzip];
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Codec::Zstd => "zst",
+            Codec::Lz4 => "lz4",
+            Codec::Gzip => "gz",
+        }
+    }
+
+    /// Guesses the codec from the last file extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "zst" => Some(Codec::Zstd),
+            "lz4" => Some(Codec::Lz4),
+            "gz" => Some(Codec::Gzip),
+            _ => None,
+        }
+    }
+
+    pub fn decoder<'a, R>(&self, reader: R) -> io::Result<Box<dyn Read + Send + 'a>>
+    where
+        R: Read + Send + 'a,
+    {
+        Ok(match self {
+            Codec::Zstd => Box::new(zstd::Decoder::new(reader)?),
+            Codec::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+            Codec::Gzip => Box::new(flate2::read::GzDecoder::new(reader)),
+        })
+    }
+
+    /// Compresses the data with the codec's checksum enabled.
+    pub fn encode(&self, data: &[u8], level: Option<i32>) -> io::Result<Vec<u8>> {
+        match self {
+            Codec::Zstd => {
+                let level = level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL);
+                let mut encoder = zstd::Encoder::new(vec![], level)?;
+                encoder.include_checksum(true)?;
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Codec::Lz4 => {
+                let info = lz4_flex::frame::FrameInfo::new().content_checksum(true);
+                let mut encoder = lz4_flex::frame::FrameEncoder::with_frame_info(info, vec![]);
+                encoder.write_all(data)?;
+                encoder.finish().map_err(io::Error::from)
+            }
+            Codec::Gzip => {
+                let level = level.map_or(flate2::Compression::default(), |level| {
+                    flate2::Compression::new(level.clamp(0, 9) as u32)
+                });
+                let mut encoder = flate2::write::GzEncoder::new(vec![], level);
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+
+    pub fn decode(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut buf = vec![];
+        self.decoder(data)?.read_to_end(&mut buf)?;
+        Ok(buf)
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct CompressOpts {
+    /// Directory containing `.bin` velodyne scans.
+    pub input_dir: PathBuf,
+    /// Directory to write the compressed scans to.
+    pub output_dir: PathBuf,
+    #[clap(long, default_value = "zstd")]
+    pub codec: Codec,
+    /// Compression level. Defaults to the codec's default.
+    #[clap(long)]
+    pub level: Option<i32>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct DecompressOpts {
+    /// Directory containing compressed velodyne scans.
+    pub input_dir: PathBuf,
+    /// Directory to write the `.bin` scans to.
+    pub output_dir: PathBuf,
+}
+
+/// Compresses every `.bin` scan in a directory. Each output is decoded
+/// again and compared against the input before it is written.
+pub fn compress_dir(opts: &CompressOpts) -> Result<()> {
+    let CompressOpts {
+        ref input_dir,
+        ref output_dir,
+        codec,
+        level,
+    } = *opts;
+
+    let inputs = list_files(input_dir, |name| name.ends_with(".bin"))?;
+    fs::create_dir_all(output_dir)?;
+
+    inputs.par_iter().try_for_each(|input_path| -> Result<()> {
+        let data = fs::read(input_path)
+            .with_context(|| format!("unable to read {}", input_path.display()))?;
+        check_scan_len(input_path, data.len())?;
+
+        let encoded = codec.encode(&data, level)?;
+        let decoded = codec.decode(&encoded)?;
+        ensure!(
+            decoded == data,
+            "round trip check failed for {}",
+            input_path.display()
+        );
+
+        let file_name = format!(
+            "{}.{}",
+            input_path.file_name().unwrap().to_string_lossy(),
+            codec.extension()
+        );
+        write_atomic(&output_dir.join(file_name), &encoded)
+    })?;
+
+    eprintln!(
+        "compressed {} scans into {}",
+        inputs.len(),
+        output_dir.display()
+    );
+    Ok(())
+}
+
+/// Decompresses every `.bin.{zst,lz4,gz}` scan in a directory. The
+/// codec's own checksum is verified while decoding.
+pub fn decompress_dir(opts: &DecompressOpts) -> Result<()> {
+    let DecompressOpts {
+        ref input_dir,
+        ref output_dir,
+    } = *opts;
+
+    let inputs = list_files(input_dir, |name| {
+        Codec::ALL
+            .iter()
+            .any(|codec| name.ends_with(&format!(".bin.{}", codec.extension())))
+    })?;
+    fs::create_dir_all(output_dir)?;
+
+    inputs.par_iter().try_for_each(|input_path| -> Result<()> {
+        let codec = Codec::from_path(input_path).unwrap();
+        let encoded = fs::read(input_path)
+            .with_context(|| format!("unable to read {}", input_path.display()))?;
+        let data = codec
+            .decode(&encoded)
+            .with_context(|| format!("corrupted scan {}", input_path.display()))?;
+        check_scan_len(input_path, data.len())?;
+
+        let file_name = input_path.file_stem().unwrap();
+        write_atomic(&output_dir.join(file_name), &data)
+    })?;
+
+    eprintln!(
+        "decompressed {} scans into {}",
+        inputs.len(),
+        output_dir.display()
+    );
+    Ok(())
+}
+
+fn list_files(dir: &Path, filter: impl Fn(&str) -> bool) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for entry in fs::read_dir(dir).with_context(|| format!("unable to read {}", dir.display()))? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if path.is_file() && filter(name) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Writes to a temporary file first so that an interrupted run never
+/// leaves a truncated scan behind.
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    fs::write(&tmp_path, data)
+        .with_context(|| format!("unable to write {}", Path::new(&tmp_path).display()))?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
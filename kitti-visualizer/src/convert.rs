@@ -0,0 +1,110 @@
+use crate::{
+    compress::{write_atomic, Codec},
+    read_pcd::{load_cloud, CloudFormat},
+    write_pcd::{write_bin, write_pcd, write_ply, PcdEncoding},
+};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use kitti_format::{KittiSource, Storage};
+use rayon::prelude::*;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone, Args)]
+pub struct ConvertOpts {
+    /// Dataset directory or KITTI zip archive containing the velodyne
+    /// directory. Repeat to combine several archives.
+    #[clap(short, long, required = true)]
+    pub input: Vec<PathBuf>,
+    /// The split directory inside zip archives.
+    #[clap(long, default_value = "training")]
+    pub split: String,
+    /// Directory to write the converted velodyne directory to.
+    pub output_dir: PathBuf,
+    /// The format of the input point clouds.
+    #[clap(long, default_value = "bin")]
+    pub from: CloudFormat,
+    /// The format to convert to. Only bin, pcd and ply can be written.
+    #[clap(long)]
+    pub to: CloudFormat,
+    /// Write ASCII instead of binary PCD files.
+    #[clap(long)]
+    pub ascii: bool,
+}
+
+/// Converts every point cloud in the `velodyne` directory of a dataset.
+/// Compressed inputs are decoded on the fly. Channels are kept by the
+/// PCD and PLY writers and dropped when writing `.bin` scans.
+pub fn convert_dataset(opts: &ConvertOpts) -> Result<()> {
+    let ConvertOpts {
+        ref input,
+        ref split,
+        ref output_dir,
+        from,
+        to,
+        ascii,
+    } = *opts;
+
+    if matches!(to, CloudFormat::Las | CloudFormat::Laz) {
+        bail!("writing {} files is not supported", to.extension());
+    }
+
+    let source = KittiSource::new(input, split)?;
+    let velodyne_dir = Path::new("velodyne");
+    let suffix = format!(".{}", from.extension());
+    let inputs: Vec<PathBuf> = source
+        .list(velodyne_dir)
+        .with_context(|| format!("unable to list velodyne in {source}"))?
+        .into_iter()
+        .filter(|path| {
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                return false;
+            };
+            name.ends_with(&suffix)
+                || Codec::ALL
+                    .iter()
+                    .any(|codec| name.ends_with(&format!("{suffix}.{}", codec.extension())))
+        })
+        .collect();
+
+    let output_dir = output_dir.join(velodyne_dir);
+    fs::create_dir_all(&output_dir)?;
+
+    inputs.par_iter().try_for_each(|input_path| -> Result<()> {
+        let cloud = load_cloud(&source, input_path, from)
+            .with_context(|| format!("unable to load {}", input_path.display()))?;
+
+        let mut data = vec![];
+        match to {
+            CloudFormat::Bin => write_bin(&mut data, &cloud)?,
+            CloudFormat::Pcd => {
+                let encoding = if ascii {
+                    PcdEncoding::Ascii
+                } else {
+                    PcdEncoding::Binary
+                };
+                write_pcd(&mut data, &cloud, encoding)?
+            }
+            CloudFormat::Ply => write_ply(&mut data, &cloud)?,
+            CloudFormat::Las | CloudFormat::Laz => unreachable!(),
+        }
+
+        // Drop the codec extension of compressed inputs before the format's
+        let uncompressed = match Codec::from_path(input_path) {
+            Some(_) => input_path.with_extension(""),
+            None => input_path.clone(),
+        };
+        let stem = uncompressed.file_stem().unwrap().to_string_lossy();
+        let file_name = format!("{stem}.{}", to.extension());
+        write_atomic(&output_dir.join(file_name), &data)
+    })?;
+
+    eprintln!(
+        "converted {} scans into {}",
+        inputs.len(),
+        output_dir.display()
+    );
+    Ok(())
+}
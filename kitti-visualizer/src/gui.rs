@@ -1,19 +1,30 @@
 use crate::{
-    utils::{get_indices_from_ann_dir, get_new_frame_data, FrameData},
+    calib_score::{alignment_score, depth_edges, EdgeMap},
+    image_view::{draw_objects, draw_points, sample_colors, side_by_side, OverlayColor},
+    point_cloud::{ACTIVE, DEVICE_ID, INSTANCE, INTENSITY, SEMANTIC},
+    utils::{
+        get_indices_from_ann_dir, get_new_frame_data, BoxMargins, CameraFrame, CloudConfig,
+        FrameData, BOX_EDGES, PRIMARY_LIDAR,
+    },
     PcdFormat,
 };
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use image::{DynamicImage, RgbImage};
 use kiss3d::{
     camera::{ArcBall, Camera},
-    event::{Action, Key, WindowEvent},
+    event::{Action, Key, Modifiers, WindowEvent},
     planar_camera::PlanarCamera,
     post_processing::PostProcessingEffect,
     renderer::Renderer,
+    resource::TextureManager,
+    scene::PlanarSceneNode,
     text::Font,
     window::{State, Window},
 };
 use kiss3d_utils::WindowPlotExt;
-use kitti_format::KittiObject;
+use kitti_format::{
+    GroundPlane, ImageProjection, KittiCalib, KittiCamera, KittiObject, KittiSource, Storage,
+};
 use nalgebra as na;
 use once_cell::sync::Lazy;
 use rayon::prelude::*;
@@ -22,10 +33,14 @@ use scarlet::{
     colormap::{ColorMap, ListedColorMap},
 };
 use std::{
-    collections::HashMap,
-    path::PathBuf,
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, iter,
+    path::{Path, PathBuf},
     rc::Rc,
-    sync::Once,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Once,
+    },
     time::{Duration, Instant},
 };
 use uluru::LRUCache;
@@ -39,13 +54,74 @@ static RANGE_VERTEX: Lazy<[na::Point3<f32>; 4]> = Lazy::new(|| {
         na::Point3::from([RANGE_META[2], RANGE_META[1], 1.]),
     ]
 });
+/// The extent and spacing in metres of the grid drawn on the road plane.
+const GROUND_GRID_RANGE: [f64; 4] = [-30.0, 40.0, -40.0, 40.0];
+const GROUND_GRID_STEP: f64 = 2.0;
+/// The colour attribute taking the colours of the camera image.
+const CAMERA_COLORS: &str = "camera";
+/// The colour attribute giving the points of each box their own colour.
+const OBJECT_COLORS: &str = "object";
+/// The colour of boxes with fewer points than the configured minimum.
+const SPARSE_BOX_COLOR: [f32; 3] = [1.0, 0.0, 1.0];
+/// The colour of points outside the camera image when colouring by
+/// [CAMERA_COLORS].
+const OUTSIDE_CAMERA_COLOR: [f32; 3] = [0.6, 0.6, 0.6];
+/// Every this many pixels of a disparity map in both directions give a
+/// pseudo-LiDAR point.
+const PSEUDO_LIDAR_STRIDE: usize = 2;
+const PSEUDO_LIDAR_COLOR: [f32; 3] = [1.0, 0.5, 0.0];
+const DEPTH_POINTS_COLOR: [f32; 3] = [0.6, 0.2, 0.8];
+/// How far the camera frustums reach, in metres.
+const FRUSTUM_DEPTH: f64 = 20.0;
+const CAMERA_AXIS_LENGTH: f64 = 1.0;
+/// The width of the camera image panel relative to the window.
+const IMAGE_PANEL_RATIO: f32 = 0.4;
+/// How far the calibration keys turn the LiDAR, in degrees, and move it,
+/// in metres.
+const TUNING_ROTATION_STEP: f64 = 0.1;
+const TUNING_TRANSLATION_STEP: f64 = 0.01;
+/// How much the margin keys grow or shrink the box margins, in metres.
+const MARGIN_STEP: f64 = 0.05;
 const FRAME_PERIOD: Duration = Duration::from_millis(100);
+/// How many frames ahead in the direction of travel are loaded in the
+/// background.
+const PREFETCH_FRAMES: usize = 4;
+const LOADER_THREADS: usize = 4;
+/// The keys toggling the points of the first nine LiDARs.
+const DEVICE_KEYS: [Key; 9] = [
+    Key::Key1,
+    Key::Key2,
+    Key::Key3,
+    Key::Key4,
+    Key::Key5,
+    Key::Key6,
+    Key::Key7,
+    Key::Key8,
+    Key::Key9,
+];
+
+/// The viewer settings given on the command line.
+pub struct GuiConfig {
+    pub screencast_dir: Option<PathBuf>,
+    pub pcd_format: PcdFormat,
+    pub cloud: CloudConfig,
+    /// Where the calibration tuned in the viewer is saved.
+    pub calib_output_dir: PathBuf,
+    /// Boxes with fewer points than this are highlighted.
+    pub min_box_points: usize,
+    pub box_margins: BoxMargins,
+    pub play_on_start: bool,
+    pub record_on_start: bool,
+}
 
 pub struct Gui {
     camera: ArcBall,
     options: GuiOptions,
     cache: GuiCache,
     data: GuiData,
+    image_panel: ImagePanel,
+    /// Set while tuning the extrinsic calibration.
+    tuning: Option<CalibTuning>,
 }
 
 type FrameIndex = usize;
@@ -53,39 +129,376 @@ type AnnotationIndex = usize;
 
 struct GuiOptions {
     mark_points_in_boxes: bool,
+    /// The attribute to colour points by, or `rgb` for the colour
+    /// channels.
+    color_attribute: String,
+    /// Devices whose points are not drawn.
+    hidden_devices: HashSet<usize>,
+    active_mode: ActiveMode,
     show_bbox: bool,
+    /// How far outside a box points still count as inside it.
+    box_margins: BoxMargins,
+    /// Whether the boxes enlarged by their margins are drawn.
+    show_margins: bool,
+    show_ground: bool,
+    show_image: bool,
+    /// What the points drawn on the camera image are coloured by, if
+    /// they are drawn.
+    image_overlay: Option<OverlayColor>,
+    /// How points outside the field of view of the camera are drawn.
+    fov_mode: FovMode,
+    /// Whether the right camera image is shown next to the left one.
+    stereo: bool,
+    /// Whether points from the disparity and depth maps are drawn.
+    show_pseudo_lidar: bool,
+    /// Whether the camera frustums and axes are drawn.
+    show_rig: bool,
     play: bool,
     record: bool,
 }
 
 struct GuiCache {
     frame_idx: FrameIndex,
-    frame_cache: HashMap<FrameIndex, FramePlot>,
-    lru: LRUCache<FrameIndex, 32>,
+    frame_cache: HashMap<AnnotationIndex, CachedFrame>,
+    lru: LRUCache<AnnotationIndex, 32>,
+    loader: FrameLoader,
+    /// Whether the frames are being stepped through forwards, which
+    /// decides the frames to prefetch.
+    forward: bool,
     next_tick: Option<Instant>,
 }
 
+/// A loaded frame and its plot data.
+struct CachedFrame {
+    data: Arc<FrameData>,
+    plot: FramePlot,
+    /// The loader generation the plot was converted in. It is converted
+    /// again from `data` once the settings change.
+    generation: usize,
+}
+
 struct GuiData {
     indices: Vec<AnnotationIndex>,
-    color_map: ListedColorMap,
-    kitti_dir: PathBuf,
-    supervisely_ann_dir: Option<PathBuf>,
+    color_map: Arc<ListedColorMap>,
+    source: Arc<KittiSource>,
+    supervisely_source: Option<Arc<KittiSource>>,
     screencast_dir: Option<PathBuf>,
     pcd_format: PcdFormat,
+    cloud_config: CloudConfig,
+    calib_output_dir: PathBuf,
+    min_box_points: usize,
+}
+
+/// The inset showing the camera image of the current frame.
+#[derive(Default)]
+struct ImagePanel {
+    node: Option<PlanarSceneNode>,
+    /// The name of the uploaded texture.
+    texture: Option<String>,
+    num_uploads: usize,
+    /// The frame whose image is in the texture, and whether it shows
+    /// the stereo pair.
+    shown: Option<(AnnotationIndex, bool)>,
+}
+
+/// Loads and converts frames on a thread pool so that stepping to a
+/// frame never blocks the render loop.
+struct FrameLoader {
+    pool: rayon::ThreadPool,
+    sender: mpsc::Sender<LoadedFrame>,
+    receiver: mpsc::Receiver<LoadedFrame>,
+    /// Frames being loaded.
+    pending: HashSet<AnnotationIndex>,
+    /// Frames that failed to load with the current settings.
+    failed: HashSet<AnnotationIndex>,
+    /// Bumped when the settings change, so that frames queued with the
+    /// old ones are skipped and those already converted are dropped.
+    generation: Arc<AtomicUsize>,
+}
+
+type LoadResult = Result<(Arc<FrameData>, FramePlot, Option<TuningFrame>)>;
+
+struct LoadedFrame {
+    generation: usize,
+    ann_idx: AnnotationIndex,
+    result: LoadResult,
+}
+
+/// Everything a frame is loaded and converted with.
+struct LoadSettings {
+    source: Arc<KittiSource>,
+    supervisely_source: Option<Arc<KittiSource>>,
+    pcd_format: PcdFormat,
+    cloud_config: CloudConfig,
+    color_map: Arc<ListedColorMap>,
+    color_attribute: Option<String>,
+    image_overlay: Option<OverlayColor>,
+    min_box_points: usize,
+    box_margins: BoxMargins,
+    /// Whether to keep the data for tuning the calibration.
+    tuning: bool,
+}
+
+impl FrameLoader {
+    fn new() -> Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(LOADER_THREADS)
+            .build()?;
+        let (sender, receiver) = mpsc::channel();
+        Ok(Self {
+            pool,
+            sender,
+            receiver,
+            pending: HashSet::new(),
+            failed: HashSet::new(),
+            generation: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// Whether a frame is being loaded or failed to load.
+    fn is_busy_or_failed(&self, ann_idx: AnnotationIndex) -> bool {
+        self.pending.contains(&ann_idx) || self.failed.contains(&ann_idx)
+    }
+
+    /// The generation of the current settings.
+    fn generation(&self) -> usize {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    /// Starts loading a frame unless it is being loaded. Frames are
+    /// loaded in the order requested. With the data of the frame given,
+    /// it is only converted.
+    fn request(
+        &mut self,
+        ann_idx: AnnotationIndex,
+        data: Option<Arc<FrameData>>,
+        settings: &Arc<LoadSettings>,
+    ) {
+        if !self.pending.insert(ann_idx) {
+            return;
+        }
+        let sender = self.sender.clone();
+        let current_generation = Arc::clone(&self.generation);
+        let generation = current_generation.load(Ordering::Relaxed);
+        let settings = Arc::clone(settings);
+        self.pool.spawn_fifo(move || {
+            if current_generation.load(Ordering::Relaxed) != generation {
+                return;
+            }
+            let result = load_frame(ann_idx, data, &settings);
+            // The receiver is gone only when the viewer is closing.
+            let _ = sender.send(LoadedFrame {
+                generation,
+                ann_idx,
+                result,
+            });
+        });
+    }
+
+    /// Forgets the frames converted or being converted with the old
+    /// settings.
+    fn reset(&mut self) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
+        self.pending.clear();
+        self.failed.clear();
+    }
+
+    /// Takes the frames loaded with the current settings since the last
+    /// call, without waiting for the rest.
+    fn finished(&mut self) -> Vec<(AnnotationIndex, LoadResult)> {
+        let generation = self.generation();
+        let loaded: Vec<_> = self
+            .receiver
+            .try_iter()
+            .filter(|loaded| loaded.generation == generation)
+            .collect();
+        loaded
+            .into_iter()
+            .map(
+                |LoadedFrame {
+                     ann_idx, result, ..
+                 }| {
+                    self.pending.remove(&ann_idx);
+                    if result.is_err() {
+                        self.failed.insert(ann_idx);
+                    }
+                    (ann_idx, result)
+                },
+            )
+            .collect()
+    }
+}
+
+/// A correction of `Tr_velo_to_cam` being tuned by hand, previewed on
+/// the image panel.
+struct CalibTuning {
+    /// The correction, applied in the camera frame.
+    delta: na::Isometry3<f64>,
+    /// The frame the correction is previewed on.
+    frame: Option<TuningFrame>,
+    /// Whether the preview is out of date.
+    dirty: bool,
+}
+
+struct TuningFrame {
+    ann_idx: AnnotationIndex,
+    /// The loaded frame, which has a camera.
+    data: Arc<FrameData>,
+    edge_map: EdgeMap,
+    depth_edges: Vec<(na::Point3<f64>, f64)>,
+    /// The alignment score of the corrected calibration.
+    score: Option<f64>,
+}
+
+impl CalibTuning {
+    fn new() -> Self {
+        Self {
+            delta: na::Isometry3::identity(),
+            frame: None,
+            dirty: true,
+        }
+    }
+
+    /// Applies the correction to a calibration.
+    fn correct(&self, calib: &KittiCalib) -> KittiCalib {
+        let mut calib = calib.clone();
+        calib.set_velo_to_cam_isometry(&(self.delta * calib.velo_to_cam_isometry()));
+        calib
+    }
+
+    /// Draws the scan onto the camera image with the corrected
+    /// calibration and updates the score.
+    fn preview(&mut self, color_by: OverlayColor, color_map: &ListedColorMap) -> Option<RgbImage> {
+        let calib = self.correct(&self.frame.as_ref()?.camera().calib);
+        let frame = self.frame.as_mut()?;
+        let projection = ImageProjection::new(&calib, KittiCamera::Cam2);
+        frame.score = alignment_score(&frame.edge_map, &frame.depth_edges, &projection);
+        let mut image = frame.camera().image.clone();
+        draw_points(
+            &mut image,
+            &frame.data.cloud,
+            &projection,
+            color_by,
+            color_map,
+        );
+        Some(image)
+    }
+
+    /// Writes the corrected calibration of the previewed frame into a
+    /// directory.
+    fn save(&self, output_dir: &Path) -> Result<PathBuf> {
+        let frame = self
+            .frame
+            .as_ref()
+            .context("the frame has no camera calibration")?;
+        fs::create_dir_all(output_dir)?;
+        let path = output_dir.join(format!("{:06}.txt", frame.ann_idx));
+        self.correct(&frame.camera().calib)
+            .write_file(&path)
+            .with_context(|| format!("unable to write {}", path.display()))?;
+        Ok(path)
+    }
+}
+
+impl TuningFrame {
+    /// Gives `None` if the frame has no camera.
+    fn new(ann_idx: AnnotationIndex, data: Arc<FrameData>) -> Option<Self> {
+        let edge_map = EdgeMap::new(&data.camera.as_ref()?.image);
+        Some(Self {
+            ann_idx,
+            edge_map,
+            depth_edges: depth_edges(data.cloud.points()),
+            data,
+            score: None,
+        })
+    }
+
+    fn camera(&self) -> &CameraFrame {
+        // Checked in TuningFrame::new
+        self.data.camera.as_ref().unwrap()
+    }
+}
+
+/// How points marked inactive by the `active` channel are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ActiveMode {
+    Show,
+    Hide,
+    Dim,
+    Highlight,
+}
+
+/// How points outside the field of view of the camera are drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FovMode {
+    Show,
+    Dim,
+    Hide,
+}
+
+impl FovMode {
+    fn next(self) -> Self {
+        match self {
+            FovMode::Show => FovMode::Dim,
+            FovMode::Dim => FovMode::Hide,
+            FovMode::Hide => FovMode::Show,
+        }
+    }
+}
+
+impl ActiveMode {
+    fn next(self) -> Self {
+        match self {
+            ActiveMode::Show => ActiveMode::Hide,
+            ActiveMode::Hide => ActiveMode::Dim,
+            ActiveMode::Dim => ActiveMode::Highlight,
+            ActiveMode::Highlight => ActiveMode::Show,
+        }
+    }
 }
 
 struct FramePlot {
     points: Vec<PointPlot>,
     bboxes: Vec<BoxPlot>,
+    /// The attributes points can be coloured by.
+    color_attributes: Vec<String>,
+    /// The number of points of each device.
+    device_counts: Vec<usize>,
+    /// The number of inactive points if the cloud has a mask.
+    num_inactive: Option<usize>,
+    /// The number of points outside the camera view if the frame has a
+    /// camera.
+    num_outside_camera: Option<usize>,
+    /// Lines of text explaining the point colours.
+    legend: Vec<(String, na::Point3<f32>)>,
+    /// Grid lines on the road plane.
+    ground_grid: Vec<[na::Point3<f32>; 2]>,
+    /// The camera image with the boxes drawn on it.
+    image: Option<RgbImage>,
+    /// The same for the right camera.
+    right_image: Option<RgbImage>,
+    /// Points back-projected from the disparity map.
+    pseudo_points: Vec<na::Point3<f32>>,
+    /// Points back-projected from the depth map.
+    depth_points: Vec<na::Point3<f32>>,
+    /// The camera frustums and axes with their colours.
+    rig_lines: Vec<([na::Point3<f32>; 2], na::Point3<f32>)>,
 }
 
 struct PointPlot {
     pos: na::Point3<f32>,
     color: na::Point3<f32>,
+    device: usize,
+    active: bool,
+    /// False for points outside the field of view of the left colour
+    /// camera.
+    in_camera: bool,
 }
 
 struct BoxPlot {
     box_edges: Vec<[na::Point3<f32>; 2]>,
+    /// The edges of the box enlarged by its margin.
+    margin_edges: Vec<[na::Point3<f32>; 2]>,
     box_color: na::Point3<f32>,
     text: String,
     text_color: na::Point3<f32>,
@@ -94,19 +507,26 @@ struct BoxPlot {
 
 impl Gui {
     pub fn new(
-        kitti_dir: PathBuf,
-        supervisely_ann_dir: Option<PathBuf>,
-        screencast_dir: Option<PathBuf>,
-        pcd_format: PcdFormat,
-        play_on_start: bool,
-        record_on_start: bool,
+        source: KittiSource,
+        supervisely_source: Option<KittiSource>,
+        config: GuiConfig,
     ) -> Result<Self> {
-        let ann_dir = kitti_dir.join("label_2");
-        let indices = get_indices_from_ann_dir(&ann_dir);
+        let GuiConfig {
+            screencast_dir,
+            pcd_format,
+            cloud: cloud_config,
+            calib_output_dir,
+            min_box_points,
+            box_margins,
+            play_on_start,
+            record_on_start,
+        } = config;
+        let ann_dir = Path::new("label_2");
+        let indices = get_indices_from_ann_dir(&source, ann_dir)?;
 
         if indices.is_empty() {
             bail!(
-                "Unable to load annotation data from {}. Is it empty?",
+                "Unable to load annotation data from {} in {source}. Is it empty?",
                 ann_dir.display()
             );
         }
@@ -155,30 +575,71 @@ impl Gui {
                 frame_idx,
                 frame_cache: HashMap::new(),
                 lru,
+                loader: FrameLoader::new()?,
+                forward: true,
                 next_tick: None,
             },
             options: GuiOptions {
                 mark_points_in_boxes: false,
+                color_attribute: INTENSITY.to_string(),
+                hidden_devices: HashSet::new(),
+                active_mode: ActiveMode::Show,
                 show_bbox: true,
+                box_margins,
+                show_margins: false,
+                show_ground: true,
+                show_image: true,
+                image_overlay: None,
+                fov_mode: FovMode::Show,
+                stereo: false,
+                show_pseudo_lidar: true,
+                show_rig: true,
                 play: play_on_start,
                 record,
             },
             data: GuiData {
                 indices,
-                color_map: ListedColorMap::plasma(),
-                kitti_dir,
-                supervisely_ann_dir,
+                color_map: Arc::new(ListedColorMap::plasma()),
+                source: Arc::new(source),
+                supervisely_source: supervisely_source.map(Arc::new),
                 screencast_dir,
                 pcd_format,
+                cloud_config,
+                calib_output_dir,
+                min_box_points,
             },
             camera,
+            image_panel: ImagePanel::default(),
+            tuning: None,
         })
     }
 
     fn render(&self, window: &mut Window) {
         let Self {
-            cache: GuiCache { frame_idx, .. },
-            data: GuiData { ref indices, .. },
+            cache:
+                GuiCache {
+                    frame_idx,
+                    ref frame_cache,
+                    ref loader,
+                    ..
+                },
+            data:
+                GuiData {
+                    ref indices,
+                    cloud_config: CloudConfig { ref lidars, .. },
+                    ..
+                },
+            options:
+                GuiOptions {
+                    mark_points_in_boxes,
+                    ref color_attribute,
+                    ref hidden_devices,
+                    active_mode,
+                    fov_mode,
+                    ref box_margins,
+                    show_margins,
+                    ..
+                },
             ..
         } = *self;
         let ann_idx = indices[frame_idx];
@@ -195,6 +656,102 @@ impl Gui {
             &na::Point3::from([0., 0., 0.]),
         );
 
+        let black = na::Point3::from([0., 0., 0.]);
+        let mut lines: Vec<(String, na::Point3<f32>)> = vec![];
+        if !frame_cache.contains_key(&ann_idx) {
+            let status = if loader.failed.contains(&ann_idx) {
+                "unable to load the frame"
+            } else {
+                "loading..."
+            };
+            lines.push((status.to_string(), black));
+        }
+        if mark_points_in_boxes {
+            lines.push((format!("color: {color_attribute}"), black));
+        }
+
+        if let Some(CachedFrame { plot: frame, .. }) = frame_cache.get(&ann_idx) {
+            if !lidars.is_empty() {
+                let devices = iter::once(PRIMARY_LIDAR).chain(lidars.iter().map(String::as_str));
+                for (device, name) in devices.enumerate() {
+                    let count = frame.device_counts.get(device).copied().unwrap_or(0);
+                    let hidden = if hidden_devices.contains(&device) {
+                        " (hidden)"
+                    } else {
+                        ""
+                    };
+                    lines.push((
+                        format!("{}. {name}: {count} points{hidden}", device + 1),
+                        black,
+                    ));
+                }
+            }
+
+            if let Some(num_inactive) = frame.num_inactive {
+                lines.push((format!("inactive: {num_inactive} ({active_mode:?})"), black));
+            }
+
+            if let Some(num_outside) = frame.num_outside_camera {
+                lines.push((
+                    format!("outside camera: {num_outside} ({fov_mode:?})"),
+                    black,
+                ));
+            }
+
+            lines.extend(frame.legend.iter().cloned());
+        }
+
+        if show_margins {
+            let format_margin = |margin: na::Vector3<f64>| {
+                format!("{:.2} {:.2} {:.2}", margin.x, margin.y, margin.z)
+            };
+            lines.push((
+                format!("box margin: {}", format_margin(box_margins.default)),
+                black,
+            ));
+            let mut classes: Vec<_> = box_margins.classes.iter().collect();
+            classes.sort_by_key(|(class, _)| *class);
+            for (class, &margin) in classes {
+                lines.push((format!("{class} margin: {}", format_margin(margin)), black));
+            }
+        }
+
+        if let Some(tuning) = &self.tuning {
+            let score = match tuning.frame.as_ref().and_then(|frame| frame.score) {
+                Some(score) => format!("{score:.4}"),
+                None => "-".to_string(),
+            };
+            let (roll, pitch, yaw) = tuning.delta.rotation.euler_angles();
+            let translation = tuning.delta.translation.vector;
+            lines.push((format!("calibration score: {score}"), black));
+            lines.push((
+                format!(
+                    "rotation: {:.2} {:.2} {:.2} deg",
+                    roll.to_degrees(),
+                    pitch.to_degrees(),
+                    yaw.to_degrees()
+                ),
+                black,
+            ));
+            lines.push((
+                format!(
+                    "translation: {:.3} {:.3} {:.3} m",
+                    translation.x, translation.y, translation.z
+                ),
+                black,
+            ));
+        }
+
+        for (nth, (text, color)) in lines.iter().enumerate() {
+            window.draw_text(
+                text,
+                &na::Point2::from([0., 50. * (nth + 1) as f32]),
+                50.0,
+                &Font::default(),
+                color,
+            );
+        }
+
         for i in 0..4 {
             window.draw_line(
                 &RANGE_VERTEX[i],
@@ -215,20 +772,80 @@ impl Gui {
                     ref frame_cache,
                     ..
                 },
+            data: GuiData { ref indices, .. },
             ..
         } = *self;
 
-        let Some(frame) = frame_cache.get(&frame_idx) else {
+        let Some(frame) = frame_cache.get(&indices[frame_idx]) else {
             return;
         };
-        self.draw_frame(frame, window);
+        self.draw_frame(&frame.plot, window);
     }
 
     fn draw_frame(&self, frame: &FramePlot, window: &mut Window) {
-        let FramePlot { points, bboxes } = frame;
+        let FramePlot {
+            points,
+            bboxes,
+            ground_grid,
+            pseudo_points,
+            depth_points,
+            rig_lines,
+            ..
+        } = frame;
+
+        let GuiOptions {
+            ref hidden_devices,
+            active_mode,
+            fov_mode,
+            ..
+        } = self.options;
+        for PointPlot {
+            pos,
+            color,
+            device,
+            active,
+            in_camera,
+        } in points
+        {
+            if hidden_devices.contains(device) {
+                continue;
+            }
+            let color = match (*active, active_mode) {
+                (true, _) | (false, ActiveMode::Show) => *color,
+                (false, ActiveMode::Hide) => continue,
+                (false, ActiveMode::Dim) => color.map(|c| c * 0.2 + 0.8),
+                (false, ActiveMode::Highlight) => [1., 0., 0.].into(),
+            };
+            let color = match (*in_camera, fov_mode) {
+                (true, _) | (false, FovMode::Show) => color,
+                (false, FovMode::Dim) => color.map(|c| c * 0.2 + 0.8),
+                (false, FovMode::Hide) => continue,
+            };
+            window.draw_point(pos, &color)
+        }
 
-        for PointPlot { pos, color } in points {
-            window.draw_point(pos, color)
+        if self.options.show_pseudo_lidar {
+            let color = PSEUDO_LIDAR_COLOR.into();
+            for pos in pseudo_points {
+                window.draw_point(pos, &color);
+            }
+            let color = DEPTH_POINTS_COLOR.into();
+            for pos in depth_points {
+                window.draw_point(pos, &color);
+            }
+        }
+
+        if self.options.show_ground {
+            let grid_color = [0.8, 0.8, 0.8].into();
+            for [p, q] in ground_grid {
+                window.draw_line(p, q, &grid_color);
+            }
+        }
+
+        if self.options.show_rig {
+            for ([p, q], color) in rig_lines {
+                window.draw_line(p, q, color);
+            }
         }
 
         if self.options.show_bbox {
@@ -241,6 +858,7 @@ impl Gui {
     fn draw_bbox(&self, bbox: &BoxPlot, window: &mut Window) {
         let BoxPlot {
             box_edges,
+            margin_edges,
             text,
             text_color,
             box_color,
@@ -251,6 +869,13 @@ impl Gui {
             window.draw_line(p, q, box_color);
         }
 
+        if self.options.show_margins {
+            let margin_color = box_color.map(|c| c * 0.5 + 0.5);
+            for [p, q] in margin_edges {
+                window.draw_line(p, q, &margin_color);
+            }
+        }
+
         self.draw_text_3d(window, text, text_pos, 50.0, &Font::default(), text_color);
     }
 
@@ -285,7 +910,19 @@ impl Gui {
             options:
                 GuiOptions {
                     mark_points_in_boxes,
+                    color_attribute,
+                    hidden_devices,
+                    active_mode,
                     show_bbox,
+                    box_margins,
+                    show_margins,
+                    show_ground,
+                    show_image,
+                    image_overlay,
+                    fov_mode,
+                    stereo,
+                    show_pseudo_lidar,
+                    show_rig,
                     play,
                     record,
                     ..
@@ -293,20 +930,27 @@ impl Gui {
             cache:
                 GuiCache {
                     frame_idx,
+                    frame_cache,
+                    loader,
+                    forward,
                     next_tick,
                     ..
                 },
             data,
+            camera,
+            tuning,
             ..
         } = self;
         let GuiData {
             ref indices,
             ref screencast_dir,
+            ref calib_output_dir,
             ..
         } = *data;
 
         let orig_frame_idx = *frame_idx;
         let mut new_frame_idx = *frame_idx;
+        let mut recolor = false;
 
         window.events().iter().for_each(|event| {
             use Action as A;
@@ -314,6 +958,40 @@ impl Gui {
             use WindowEvent as E;
 
             match event.value {
+                E::Key(K::K, A::Press, _) => {
+                    *tuning = match tuning {
+                        Some(_) => None,
+                        None => Some(CalibTuning::new()),
+                    };
+                    // Enter saves the calibration while tuning instead of
+                    // resetting the view.
+                    camera.rebind_reset_key(match tuning {
+                        Some(_) => None,
+                        None => Some(K::Return),
+                    });
+                    recolor = true;
+                }
+                E::Key(
+                    key @ (K::Insert | K::Delete | K::Home | K::End | K::PageUp | K::PageDown),
+                    A::Press,
+                    modifiers,
+                ) if tuning.is_some() => {
+                    let tuning = tuning.as_mut().unwrap();
+                    let nudge = tuning_nudge(key, modifiers.contains(Modifiers::Shift));
+                    tuning.delta = nudge * tuning.delta;
+                    tuning.dirty = true;
+                }
+                E::Key(K::Back, A::Press, _) if tuning.is_some() => {
+                    let tuning = tuning.as_mut().unwrap();
+                    tuning.delta = na::Isometry3::identity();
+                    tuning.dirty = true;
+                }
+                E::Key(K::Return, A::Press, _) if tuning.is_some() => {
+                    match tuning.as_ref().unwrap().save(calib_output_dir) {
+                        Ok(path) => eprintln!("saved the calibration to {}", path.display()),
+                        Err(err) => eprintln!("unable to save the calibration: {err:#}"),
+                    }
+                }
                 E::Key(K::R, A::Press, _) => {
                     if let (false, None) = (*record, screencast_dir) {
                         static WARN: Once = Once::new();
@@ -326,18 +1004,81 @@ impl Gui {
                 }
                 E::Key(K::I, A::Press, _) => {
                     *mark_points_in_boxes = !*mark_points_in_boxes;
+                    recolor = true;
+                }
+                E::Key(K::C, A::Press, _) => {
+                    let ann_idx = indices[*frame_idx];
+                    if let Some(frame) = frame_cache.get(&ann_idx) {
+                        let attributes = &frame.plot.color_attributes;
+                        let next = attributes
+                            .iter()
+                            .position(|name| name == color_attribute)
+                            .map_or(0, |pos| (pos + 1) % attributes.len());
+                        *color_attribute = attributes[next].clone();
+                        *mark_points_in_boxes = true;
+                        recolor = true;
+                    }
                 }
                 E::Key(K::Space, A::Press, _) => {
                     *play = !*play;
+                    *forward = true;
                 }
                 E::Key(K::B, A::Press, _) => {
                     *show_bbox = !*show_bbox;
                 }
+                E::Key(K::O, A::Press, _) => {
+                    *show_margins = !*show_margins;
+                }
+                E::Key(K::LBracket, A::Press, _) => {
+                    box_margins.grow(-MARGIN_STEP);
+                    recolor = true;
+                }
+                E::Key(K::RBracket, A::Press, _) => {
+                    box_margins.grow(MARGIN_STEP);
+                    recolor = true;
+                }
+                E::Key(K::G, A::Press, _) => {
+                    *show_ground = !*show_ground;
+                }
+                E::Key(K::V, A::Press, _) => {
+                    *show_image = !*show_image;
+                }
+                E::Key(K::P, A::Press, _) => {
+                    *image_overlay = match image_overlay {
+                        None => Some(OverlayColor::Depth),
+                        Some(OverlayColor::Depth) => Some(OverlayColor::Intensity),
+                        Some(OverlayColor::Intensity) => None,
+                    };
+                    recolor = true;
+                }
+                E::Key(K::H, A::Press, _) => {
+                    *fov_mode = fov_mode.next();
+                }
+                E::Key(K::S, A::Press, _) => {
+                    *stereo = !*stereo;
+                }
+                E::Key(K::D, A::Press, _) => {
+                    *show_pseudo_lidar = !*show_pseudo_lidar;
+                }
+                E::Key(K::F, A::Press, _) => {
+                    *show_rig = !*show_rig;
+                }
+                E::Key(K::M, A::Press, _) => {
+                    *active_mode = active_mode.next();
+                }
+                E::Key(key, A::Press, _) if DEVICE_KEYS.contains(&key) => {
+                    let device = DEVICE_KEYS.iter().position(|&k| k == key).unwrap();
+                    if !hidden_devices.remove(&device) {
+                        hidden_devices.insert(device);
+                    }
+                }
                 E::Key(K::Left, A::Press, _) => {
-                    new_frame_idx = (new_frame_idx - 1).rem_euclid(indices.len());
+                    new_frame_idx = (new_frame_idx + indices.len() - 1) % indices.len();
+                    *forward = false;
                 }
                 E::Key(K::Right, A::Press, _) => {
-                    new_frame_idx = (new_frame_idx + 1).rem_euclid(indices.len());
+                    new_frame_idx = (new_frame_idx + 1) % indices.len();
+                    *forward = true;
                 }
                 E::Key(K::Escape, A::Press, _) => {
                     window.close();
@@ -348,6 +1089,14 @@ impl Gui {
 
         if orig_frame_idx != new_frame_idx {
             *play = false;
+            // The preview needs the scan and image of the new frame.
+            recolor |= tuning.is_some();
+        }
+
+        // The frames on screen and in the cache stay until they are
+        // converted again with the new settings.
+        if recolor {
+            loader.reset();
         }
 
         if *play {
@@ -355,7 +1104,12 @@ impl Gui {
 
             match next_tick {
                 Some(next_tick) => {
-                    if now >= *next_tick {
+                    // Wait for the frame on screen instead of skipping
+                    // past frames still loading.
+                    let ann_idx = indices[new_frame_idx];
+                    let shown =
+                        frame_cache.contains_key(&ann_idx) || loader.failed.contains(&ann_idx);
+                    if now >= *next_tick && shown {
                         while now >= *next_tick {
                             *next_tick += FRAME_PERIOD;
                         }
@@ -376,62 +1130,239 @@ impl Gui {
         *frame_idx = new_frame_idx;
     }
 
-    fn update(&mut self) {
+    /// Shows the camera image of the current frame in the lower right
+    /// corner, uploading it as a texture when the frame changes.
+    fn update_image_panel(&mut self, window: &mut Window) {
         let Self {
-            cache: GuiCache { frame_idx, .. },
+            cache:
+                GuiCache {
+                    frame_idx,
+                    ref frame_cache,
+                    ..
+                },
+            data: GuiData { ref indices, .. },
+            options: GuiOptions {
+                show_image, stereo, ..
+            },
+            ref mut image_panel,
             ..
         } = *self;
+        let ann_idx = indices[frame_idx];
+        let frame = frame_cache
+            .get(&ann_idx)
+            .map(|frame| &frame.plot)
+            .filter(|_| show_image);
+        let images = frame.and_then(|frame| {
+            let left = frame.image.as_ref()?;
+            let right = frame.right_image.as_ref().filter(|_| stereo);
+            Some((left, right))
+        });
+
+        let Some((left, right)) = images else {
+            if let Some(node) = &mut image_panel.node {
+                node.set_visible(false);
+            }
+            return;
+        };
+
+        let ImagePanel {
+            node,
+            texture,
+            num_uploads,
+            shown,
+        } = image_panel;
+        let node = node.get_or_insert_with(|| window.add_rectangle(1.0, 1.0));
+
+        if *shown != Some((ann_idx, right.is_some())) {
+            let image = match right {
+                Some(right) => side_by_side(left, right),
+                None => left.clone(),
+            };
+            // Textures are cached by name, so each upload needs a new one.
+            let name = format!("camera_image_{num_uploads}");
+            *num_uploads += 1;
+            let new_texture = TextureManager::get_global_manager(|manager| {
+                if let Some(prev) = texture.take() {
+                    manager.remove(&prev);
+                }
+                manager.add_image(DynamicImage::ImageRgb8(image.clone()), &name)
+            });
+            node.set_texture(new_texture);
+            *texture = Some(name);
+            *shown = Some((ann_idx, right.is_some()));
+        }
+
+        // The planar view is centred and measured in logical pixels. The
+        // stereo pair gets twice the width.
+        let (num_images, image_width) = match right {
+            Some(right) => (2.0, left.width() + right.width()),
+            None => (1.0, left.width()),
+        };
+        let image_height = left.height().max(right.map_or(0, |right| right.height()));
+        let window_size = window.size().cast::<f32>() / window.scale_factor() as f32;
+        let width = window_size.x * (IMAGE_PANEL_RATIO * num_images).min(1.0);
+        let height = width * image_height as f32 / image_width as f32;
+        node.set_local_scale(width, height);
+        node.set_local_translation(na::Translation2::new(
+            (window_size.x - width) / 2.0,
+            (height - window_size.y) / 2.0,
+        ));
+        node.set_visible(true);
+    }
 
-        use std::collections::hash_map::Entry;
+    /// Takes the frames loaded in the background and requests the
+    /// current frame and the ones around it. Never waits for a frame.
+    fn update(&mut self) {
         let Self {
             cache:
                 GuiCache {
+                    frame_idx,
                     ref mut frame_cache,
                     ref mut lru,
+                    ref mut loader,
+                    forward,
                     ..
                 },
             data:
                 GuiData {
                     ref indices,
-                    ref kitti_dir,
-                    ref supervisely_ann_dir,
+                    ref source,
+                    ref supervisely_source,
                     ref color_map,
                     pcd_format,
+                    ref cloud_config,
+                    min_box_points,
                     ..
                 },
             options:
                 GuiOptions {
-                    mark_points_in_boxes: draw_in_intensity,
+                    mark_points_in_boxes,
+                    ref color_attribute,
+                    image_overlay,
+                    ref box_margins,
                     ..
                 },
+            ref mut tuning,
+            ref mut image_panel,
             ..
         } = *self;
         let ann_idx = indices[frame_idx];
+        let generation = loader.generation();
 
-        if let Entry::Vacant(entry) = frame_cache.entry(ann_idx) {
-            let result = get_new_frame_data(
-                ann_idx as i32,
-                kitti_dir,
-                supervisely_ann_dir.as_deref(),
-                pcd_format,
-            );
-
-            let frame_data = match result {
-                Ok(frame_data) => frame_data,
+        for (loaded_idx, result) in loader.finished() {
+            let (data, plot, tuning_frame) = match result {
+                Ok(loaded) => loaded,
                 Err(err) => {
-                    eprintln!("fail to load frame {}: {err}", ann_idx);
-                    return;
+                    eprintln!("fail to load frame {loaded_idx}: {err:#}");
+                    continue;
                 }
             };
+            if let (Some(tuning), Some(tuning_frame)) = (tuning.as_mut(), tuning_frame) {
+                if loaded_idx == ann_idx {
+                    tuning.frame = Some(tuning_frame);
+                    tuning.dirty = true;
+                }
+            }
+            if loaded_idx == ann_idx {
+                image_panel.shown = None;
+            }
+            let cached = CachedFrame {
+                data,
+                plot,
+                generation,
+            };
+            if frame_cache.insert(loaded_idx, cached).is_none() {
+                if let Some(evicted) = lru.insert(loaded_idx) {
+                    frame_cache.remove(&evicted);
+                }
+            }
+        }
+        // Keep the frame on screen from being evicted by prefetched ones.
+        lru.find(|&cached| cached == ann_idx);
+
+        // Only the frame on screen can be tuned, so nothing is prefetched
+        // while tuning.
+        let num_ahead = if tuning.is_some() { 0 } else { PREFETCH_FRAMES };
+        // Playback stops at the last frame, so the prefetch does not wrap.
+        let step: isize = if forward { 1 } else { -1 };
+        let ahead = (1..=num_ahead as isize).map(|nth| step * nth);
+        let behind = (num_ahead > 0).then_some(-step);
+        // Frames loaded with old settings are converted again from their
+        // cached data.
+        let wanted: Vec<(AnnotationIndex, Option<Arc<FrameData>>)> = iter::once(0)
+            .chain(ahead)
+            .chain(behind)
+            .filter_map(|offset| frame_idx.checked_add_signed(offset))
+            .filter(|&idx| idx < indices.len())
+            .map(|idx| indices[idx])
+            .filter(|&idx| !loader.is_busy_or_failed(idx))
+            .filter_map(|idx| match frame_cache.get(&idx) {
+                None => Some((idx, None)),
+                Some(cached) if cached.generation != generation => {
+                    Some((idx, Some(Arc::clone(&cached.data))))
+                }
+                Some(_) => None,
+            })
+            .collect();
+        if wanted.is_empty() {
+            return;
+        }
 
-            let frame_plot = convert_frame(&frame_data, draw_in_intensity, color_map);
-
-            entry.insert(frame_plot);
+        let settings = Arc::new(LoadSettings {
+            source: Arc::clone(source),
+            supervisely_source: supervisely_source.clone(),
+            pcd_format,
+            cloud_config: cloud_config.clone(),
+            color_map: Arc::clone(color_map),
+            color_attribute: mark_points_in_boxes.then(|| color_attribute.clone()),
+            image_overlay,
+            min_box_points,
+            box_margins: box_margins.clone(),
+            tuning: tuning.is_some(),
+        });
+        for (idx, data) in wanted {
+            loader.request(idx, data, &settings);
+        }
+    }
 
-            if let Some(frame_idx_to_rm) = lru.insert(frame_idx) {
-                self.cache.frame_cache.remove(&frame_idx_to_rm);
-            }
+    /// Redraws the calibration preview onto the image panel after the
+    /// correction or the frame changed.
+    fn update_tuning(&mut self) {
+        let Self {
+            cache:
+                GuiCache {
+                    frame_idx,
+                    ref mut frame_cache,
+                    ..
+                },
+            data:
+                GuiData {
+                    ref indices,
+                    ref color_map,
+                    ..
+                },
+            options: GuiOptions { image_overlay, .. },
+            ref mut tuning,
+            ref mut image_panel,
+            ..
+        } = *self;
+        let ann_idx = indices[frame_idx];
+        let Some(tuning) = tuning.as_mut().filter(|tuning| tuning.dirty) else {
+            return;
+        };
+        let Some(CachedFrame { plot: frame, .. }) = frame_cache.get_mut(&ann_idx) else {
+            return;
+        };
+        if tuning
+            .frame
+            .as_ref()
+            .is_some_and(|frame| frame.ann_idx == ann_idx)
+        {
+            let color_by = image_overlay.unwrap_or(OverlayColor::Depth);
+            frame.image = tuning.preview(color_by, color_map);
+            image_panel.shown = None;
         }
+        tuning.dirty = false;
     }
 }
 
@@ -439,6 +1370,8 @@ impl State for Gui {
     fn step(&mut self, window: &mut Window) {
         self.process_events(window);
         self.update();
+        self.update_tuning();
+        self.update_image_panel(window);
         self.render(window);
 
         match (self.options.record, &self.data.screencast_dir) {
@@ -465,62 +1398,381 @@ impl State for Gui {
     }
 }
 
+/// Loads a frame unless its data is given and converts it into plot
+/// data, keeping the data for tuning the calibration if asked to.
+fn load_frame(
+    ann_idx: AnnotationIndex,
+    data: Option<Arc<FrameData>>,
+    settings: &LoadSettings,
+) -> LoadResult {
+    let LoadSettings {
+        ref source,
+        ref supervisely_source,
+        pcd_format,
+        ref cloud_config,
+        ref color_map,
+        ref color_attribute,
+        image_overlay,
+        min_box_points,
+        ref box_margins,
+        tuning,
+    } = *settings;
+
+    let frame_data = match data {
+        Some(data) => data,
+        None => Arc::new(get_new_frame_data(
+            ann_idx as i32,
+            source.as_ref(),
+            supervisely_source
+                .as_deref()
+                .map(|source| source as &dyn Storage),
+            pcd_format,
+            cloud_config,
+            box_margins,
+        )?),
+    };
+    let frame_plot = convert_frame(
+        &frame_data,
+        color_attribute.as_deref(),
+        image_overlay,
+        min_box_points,
+        box_margins,
+        color_map,
+    );
+    let tuning_frame = tuning
+        .then(|| TuningFrame::new(ann_idx, Arc::clone(&frame_data)))
+        .flatten();
+    Ok((frame_data, frame_plot, tuning_frame))
+}
+
+/// Converts a frame into plot data. Points are coloured by
+/// `color_attribute` if given, falling back to the intensity if the
+/// cloud has no such attribute. With `image_overlay`, the points are
+/// also drawn on the camera image. Boxes with fewer than
+/// `min_box_points` points are highlighted.
 fn convert_frame(
     frame_data: &FrameData,
-    draw_in_intensity: bool,
+    color_attribute: Option<&str>,
+    image_overlay: Option<OverlayColor>,
+    min_box_points: usize,
+    box_margins: &BoxMargins,
     color_map: &ListedColorMap,
 ) -> FramePlot {
     // let Some(frame_data) = frame_cache.get(&frame_idx) else {
     //     return;
     // };
 
-    let points_in_range = frame_data.points_in_range.par_iter().map(|point| {
-        let color = if draw_in_intensity {
-            let color: RGBColor = color_map.transform_single((point.intensity / 255. * 10.) as f64);
-            na::Point3::from([color.r, color.g, color.b]).cast()
-        } else {
-            na::Point3::from([0.0, 0.0, 1.0])
-        };
-        (&point.point, color)
+    let cloud = &frame_data.cloud;
+    let membership = frame_data.membership(box_margins);
+    let rgb = cloud.rgb();
+    let color_attribute = color_attribute.map(|name| match name {
+        "rgb" if rgb.is_some() => name,
+        CAMERA_COLORS if frame_data.camera.is_some() => name,
+        OBJECT_COLORS if !frame_data.objects.is_empty() => name,
+        name if cloud.has_attribute(name) => name,
+        _ => INTENSITY,
+    });
+    let range = match color_attribute {
+        Some(INTENSITY | "rgb" | CAMERA_COLORS | OBJECT_COLORS) | None => None,
+        Some(name) => cloud.channel(name).unwrap().range(),
+    };
+    let camera_colors = match (color_attribute, &frame_data.camera) {
+        (Some(CAMERA_COLORS), Some(CameraFrame { image, calib, .. })) => {
+            let projection = ImageProjection::new(calib, KittiCamera::Cam2);
+            Some(sample_colors(image, cloud, &projection))
+        }
+        _ => None,
+    };
+
+    let fov_mask = frame_data.camera.as_ref().map(|camera| {
+        let projection = ImageProjection::new(&camera.calib, KittiCamera::Cam2);
+        projection.image_mask(cloud.points(), camera.image.width(), camera.image.height())
     });
 
-    let points_out_range = frame_data.points_out_range.par_iter().map(|point| {
-        let color = if draw_in_intensity {
-            let color: RGBColor = color_map.transform_single((point.intensity / 255. * 10.) as f64);
-            na::Point3::from([color.r, color.g, color.b]).cast()
-        } else {
-            na::Point3::from([0.0, 0.0, 1.0])
+    let device_ids = cloud.channel(DEVICE_ID);
+
+    let convert_point = |idx: usize| {
+        let color = match (color_attribute, rgb) {
+            (None, _) => na::Point3::from([0.0, 0.0, 1.0]),
+            (Some("rgb"), Some(rgb)) => {
+                na::Point3::from(rgb.map(|channel| (channel.get(idx) / 255.0) as f32))
+            }
+            (Some(SEMANTIC), _) => {
+                let semantic = cloud.value(SEMANTIC, idx).unwrap() as u16;
+                semantic_color(semantic)
+            }
+            (Some(INSTANCE), _) => {
+                let instance = cloud.value(INSTANCE, idx).unwrap() as u16;
+                instance_color(instance, color_map)
+            }
+            (Some(CAMERA_COLORS), _) => match camera_colors.as_ref().unwrap()[idx] {
+                Some(color) => na::Point3::from(color.map(|c| c as f32 / 255.)),
+                None => OUTSIDE_CAMERA_COLOR.into(),
+            },
+            (Some(OBJECT_COLORS), _) => object_color(membership.point_boxes[idx], color_map),
+            (Some(name), _) => {
+                let value = cloud.value(name, idx).unwrap();
+                let scaled = match range {
+                    Some((min, max)) if max > min => (value - min) / (max - min),
+                    Some(_) => 0.0,
+                    None => value / 255. * 10.,
+                };
+                let color: RGBColor = color_map.transform_single(scaled);
+                na::Point3::from([color.r, color.g, color.b]).cast()
+            }
         };
-        (&point.point, color)
+        PointPlot {
+            pos: cloud.position(idx),
+            color,
+            device: device_ids.map_or(0, |ids| ids.get(idx) as usize),
+            active: cloud.is_active(idx),
+            in_camera: fov_mask.as_ref().is_none_or(|mask| mask[idx]),
+        }
+    };
+    let points_with_mask = || membership.point_boxes.par_iter().enumerate();
+
+    let points_in_range = points_with_mask()
+        .filter(|(_, bbox)| bbox.is_some())
+        .map(|(idx, _)| convert_point(idx));
+    let points_out_range = points_with_mask()
+        .filter(|(_, bbox)| bbox.is_none())
+        .map(|(idx, _)| convert_point(idx));
+
+    let points: Vec<_> = points_in_range.chain(points_out_range).collect();
+
+    let bboxes = convert_objects_in_pcd(
+        &frame_data.objects,
+        &membership.box_counts,
+        min_box_points,
+        box_margins,
+        frame_data.ground_plane.as_ref(),
+        (color_attribute == Some(OBJECT_COLORS)).then_some(color_map),
+    );
+    let ground_grid = frame_data
+        .ground_plane
+        .as_ref()
+        .map_or_else(Vec::new, ground_grid_lines);
+    let draw_camera_image = |image: &RgbImage, camera: KittiCamera, calib: &KittiCalib| {
+        let mut image = image.clone();
+        let projection = ImageProjection::new(calib, camera);
+        if let Some(color_by) = image_overlay {
+            draw_points(&mut image, cloud, &projection, color_by, color_map);
+        }
+        draw_objects(&mut image, &frame_data.objects, &projection);
+        image
+    };
+    let camera = frame_data.camera.as_ref();
+    let image =
+        camera.map(|camera| draw_camera_image(&camera.image, KittiCamera::Cam2, &camera.calib));
+    let right_image = camera.and_then(|camera| {
+        let right_image = camera.right_image.as_ref()?;
+        Some(draw_camera_image(
+            right_image,
+            KittiCamera::Cam3,
+            &camera.calib,
+        ))
     });
+    let pseudo_points = camera
+        .and_then(|camera| {
+            let disparity = camera.disparity.as_ref()?;
+            let points = disparity.disparity_to_points(&camera.calib, PSEUDO_LIDAR_STRIDE);
+            Some(points.iter().map(|point| point.cast()).collect())
+        })
+        .unwrap_or_default();
+    let depth_points = camera
+        .and_then(|camera| {
+            let depth = camera.depth.as_ref()?;
+            let points = depth.depth_to_points(&camera.calib, PSEUDO_LIDAR_STRIDE);
+            Some(points.iter().map(|point| point.cast()).collect())
+        })
+        .unwrap_or_default();
+    let rig_lines = camera.map_or_else(Vec::new, camera_rig_lines);
 
-    let points: Vec<_> = points_in_range
-        .chain(points_out_range)
-        .map(|(&pos, color)| PointPlot { pos, color })
-        .collect();
+    let mut color_attributes = cloud.attribute_names();
+    if rgb.is_some() {
+        color_attributes.push("rgb".to_string());
+    }
+    if frame_data.camera.is_some() {
+        color_attributes.push(CAMERA_COLORS.to_string());
+    }
+    if !frame_data.objects.is_empty() {
+        color_attributes.push(OBJECT_COLORS.to_string());
+    }
 
-    let bboxes = convert_objects_in_pcd(&frame_data.objects, &frame_data.num_points_map);
-
-    FramePlot { points, bboxes }
-}
-
-fn convert_objects_in_pcd(objects: &[KittiObject], _num_points_map: &[usize]) -> Vec<BoxPlot> {
-    const EDGE_RELATION: &[(usize, usize)] = &[
-        (0, 1),
-        (0, 2),
-        (1, 3),
-        (2, 3),
-        (4, 5),
-        (4, 6),
-        (5, 7),
-        (6, 7),
-        (0, 4),
-        (1, 5),
-        (2, 6),
-        (3, 7),
-        (1, 7),
-        (3, 5),
+    let mut device_counts = vec![];
+    if let Some(device_ids) = device_ids {
+        for idx in 0..device_ids.len() {
+            let device = device_ids.get(idx) as usize;
+            if device >= device_counts.len() {
+                device_counts.resize(device + 1, 0);
+            }
+            device_counts[device] += 1;
+        }
+    }
+
+    let num_inactive = cloud
+        .channel(ACTIVE)
+        .map(|_| points.iter().filter(|point| !point.active).count());
+    let num_outside_camera = fov_mask
+        .as_ref()
+        .map(|mask| mask.iter().filter(|&&inside| !inside).count());
+
+    let mut legend = vec![];
+    if let (Some(SEMANTIC), Some(semantic)) = (color_attribute, cloud.channel(SEMANTIC)) {
+        let mut class_counts: BTreeMap<u16, usize> = BTreeMap::new();
+        for idx in 0..semantic.len() {
+            *class_counts.entry(semantic.get(idx) as u16).or_default() += 1;
+        }
+        for (semantic, count) in class_counts {
+            let name = kitti_format::semantic_class(semantic)
+                .map_or_else(|| semantic.to_string(), |(name, _)| name.to_string());
+            legend.push((format!("{name}: {count}"), semantic_color(semantic)));
+        }
+    }
+
+    FramePlot {
+        points,
+        bboxes,
+        color_attributes,
+        device_counts,
+        num_inactive,
+        num_outside_camera,
+        legend,
+        ground_grid,
+        image,
+        right_image,
+        pseudo_points,
+        depth_points,
+        rig_lines,
+    }
+}
+
+/// The correction a calibration key applies. Insert/Delete, Home/End
+/// and PageUp/PageDown turn the LiDAR about the x, y and z axes of the
+/// camera, or move it along them with shift.
+fn tuning_nudge(key: Key, shift: bool) -> na::Isometry3<f64> {
+    let axis = match key {
+        Key::Insert => na::Vector3::x(),
+        Key::Delete => -na::Vector3::x(),
+        Key::Home => na::Vector3::y(),
+        Key::End => -na::Vector3::y(),
+        Key::PageUp => na::Vector3::z(),
+        Key::PageDown => -na::Vector3::z(),
+        _ => na::Vector3::zeros(),
+    };
+    if shift {
+        na::Isometry3::from(na::Translation3::from(axis * TUNING_TRANSLATION_STEP))
+    } else {
+        na::Isometry3::rotation(axis * TUNING_ROTATION_STEP.to_radians())
+    }
+}
+
+/// Outlines the frustum of each camera up to [FRUSTUM_DEPTH] and draws
+/// its axes, x in red, y in green and z in blue.
+fn camera_rig_lines(camera: &CameraFrame) -> Vec<([na::Point3<f32>; 2], na::Point3<f32>)> {
+    let CameraFrame {
+        image,
+        calib,
+        right_image,
+        ..
+    } = camera;
+    let rect_to_velo = calib.get_transformation_from_rectified_camera_to_velodyne();
+    let grey = na::Point3::from([0.5, 0.5, 0.5]);
+    let teal = na::Point3::from([0.0, 0.6, 0.6]);
+    let cameras = [
+        (KittiCamera::Cam0, image, grey),
+        (KittiCamera::Cam1, image, grey),
+        (KittiCamera::Cam2, image, teal),
+        (
+            KittiCamera::Cam3,
+            right_image.as_ref().unwrap_or(image),
+            teal,
+        ),
     ];
+    let axes: [(na::Vector3<f64>, na::Point3<f32>); 3] = [
+        (na::Vector3::x(), [1., 0., 0.].into()),
+        (na::Vector3::y(), [0., 1., 0.].into()),
+        (na::Vector3::z(), [0., 0., 1.].into()),
+    ];
+
+    let mut lines = vec![];
+    for (camera, image, color) in cameras {
+        // Cameras missing from the calibration have no inverse.
+        let projection = ImageProjection::new(calib, camera);
+        let (Some(center), Some(corners)) = (
+            projection.camera_center(),
+            projection.frustum_corners(image.width(), image.height(), FRUSTUM_DEPTH),
+        ) else {
+            continue;
+        };
+
+        for (nth, corner) in corners.iter().enumerate() {
+            let next = &corners[(nth + 1) % corners.len()];
+            lines.push(([center.cast(), corner.cast()], color));
+            lines.push(([corner.cast(), next.cast()], color));
+        }
+        for (axis, axis_color) in axes {
+            let end = center + rect_to_velo.rotation * axis * CAMERA_AXIS_LENGTH;
+            lines.push(([center.cast(), end.cast()], axis_color));
+        }
+    }
+    lines
+}
+
+/// Lays a grid over the road plane, see [GROUND_GRID_RANGE].
+fn ground_grid_lines(plane: &GroundPlane) -> Vec<[na::Point3<f32>; 2]> {
+    let [x_min, x_max, y_min, y_max] = GROUND_GRID_RANGE;
+    let steps = |min: f64, max: f64| {
+        let num_steps = ((max - min) / GROUND_GRID_STEP) as usize;
+        (0..=num_steps).map(move |nth| min + nth as f64 * GROUND_GRID_STEP)
+    };
+    let line = |from: (f64, f64), to: (f64, f64)| -> Option<[na::Point3<f32>; 2]> {
+        Some([
+            plane.point_at(from.0, from.1)?.cast(),
+            plane.point_at(to.0, to.1)?.cast(),
+        ])
+    };
+
+    let along_x = steps(y_min, y_max).filter_map(|y| line((x_min, y), (x_max, y)));
+    let along_y = steps(x_min, x_max).filter_map(|x| line((x, y_min), (x, y_max)));
+    along_x.chain(along_y).collect()
+}
+
+/// The SemanticKITTI colour of a class.
+fn semantic_color(semantic: u16) -> na::Point3<f32> {
+    let color = kitti_format::semantic_class(semantic).map_or([0, 0, 0], |(_, color)| color);
+    na::Point3::from(color.map(|c| c as f32 / 255.))
+}
+
+/// Spreads instance ids over the colour map. Points without an instance
+/// are grey.
+fn instance_color(instance: u16, color_map: &ListedColorMap) -> na::Point3<f32> {
+    if instance == 0 {
+        return [0.6, 0.6, 0.6].into();
+    }
+    let color: RGBColor = color_map.transform_single((instance as f64 * 0.618034).fract());
+    na::Point3::from([color.r, color.g, color.b]).cast()
+}
+
+/// The colour of the points in a box when colouring by
+/// [OBJECT_COLORS], grey for points in no box.
+fn object_color(bbox: Option<usize>, color_map: &ListedColorMap) -> na::Point3<f32> {
+    instance_color(bbox.map_or(0, |idx| idx as u16 + 1), color_map)
+}
+
+/// Builds the box plots. The label of each box gives the number of
+/// points inside, and boxes with fewer than `min_box_points` points are
+/// drawn in [SPARSE_BOX_COLOR]. With a road plane, the label also gives
+/// the height of its lowest corner above the road. With `object_colors`,
+/// the other boxes take the colour of their points.
+fn convert_objects_in_pcd(
+    objects: &[KittiObject],
+    num_points_map: &[usize],
+    min_box_points: usize,
+    box_margins: &BoxMargins,
+    ground_plane: Option<&GroundPlane>,
+    object_colors: Option<&ListedColorMap>,
+) -> Vec<BoxPlot> {
     // let rect2velo = {
     //     let rect_to_cam = na::UnitQuaternion::from_matrix(&calib.r0_rect).inverse();
     //     let velo_to_cam_rot = na::UnitQuaternion::from_matrix(&na::Matrix3::from_row_slice(&[
@@ -545,18 +1797,41 @@ fn convert_objects_in_pcd(objects: &[KittiObject], _num_points_map: &[usize]) ->
 
     let box_plots: Vec<_> = objects
         .par_iter()
-        .map(|obj| {
+        .enumerate()
+        .map(|(idx, obj)| {
             let vertices = obj.bbox3d.vertices();
+            let num_points = num_points_map[idx];
 
-            let box_color: na::Point3<f32> = [0., 1., 0.].into();
-            let box_edges: Vec<_> = EDGE_RELATION
+            let box_color: na::Point3<f32> = match object_colors {
+                _ if num_points < min_box_points => SPARSE_BOX_COLOR.into(),
+                Some(color_map) => object_color(Some(idx), color_map),
+                None => [0., 1., 0.].into(),
+            };
+            let box_edges: Vec<_> = BOX_EDGES
                 .par_iter()
                 .copied()
                 .map(|(from_idx, to_idx)| [vertices[from_idx].cast(), vertices[to_idx].cast()])
                 .collect();
+            let margin_vertices = obj.bbox3d.enlarged(&box_margins.of(&obj.class)).vertices();
+            let margin_edges: Vec<_> = BOX_EDGES
+                .iter()
+                .map(|&(from_idx, to_idx)| {
+                    [
+                        margin_vertices[from_idx].cast(),
+                        margin_vertices[to_idx].cast(),
+                    ]
+                })
+                .collect();
 
-            // let num_points = num_points_map[idx];
-            let text = format!("{:?}, {:.2}", obj.class.clone(), obj.bbox3d.extents.x);
+            let mut text = format!(
+                "{:?}, {:.2}, n={num_points}",
+                obj.class.clone(),
+                obj.bbox3d.extents.x
+            );
+            if let Some(plane) = ground_plane {
+                let height = plane.height_of_box(&obj.bbox3d);
+                text.push_str(&format!(", h={height:.2}"));
+            }
             let text_color: na::Point3<f32> = if obj.object_key.is_some() {
                 [1., 0., 0.]
             } else {
@@ -567,6 +1842,7 @@ fn convert_objects_in_pcd(objects: &[KittiObject], _num_points_map: &[usize]) ->
 
             BoxPlot {
                 box_edges,
+                margin_edges,
                 text,
                 text_color,
                 box_color,
@@ -577,129 +1853,3 @@ fn convert_objects_in_pcd(objects: &[KittiObject], _num_points_map: &[usize]) ->
 
     box_plots
 }
-
-// pub fn draw_2d_bbox(obj: &KittiObject, image: &mut cv_core::Mat) {
-//     let bbox2d = &obj.bbox2d;
-//     let bbox3d = &obj.bbox3d;
-//     let rect = cv_core::Rect {
-//         x: bbox2d.left as i32,
-//         y: bbox2d.top as i32,
-//         height: bbox2d.height as i32,
-//         width: bbox2d.width as i32,
-//     };
-
-//     imgproc::rectangle(
-//         image,
-//         rect,
-//         cv_core::Scalar::new(0.0, 255.0, 0.0, 0.0),
-//         2, // thickness
-//         imgproc::LINE_8,
-//         0, // shift
-//     )
-//     .unwrap();
-
-//     imgproc::put_text(
-//         image,
-//         &format!(
-//             "{:.2} m",
-//             na::Vector3::from([bbox3d.center().x, bbox3d.center().y, bbox3d.center().z]).norm()
-//         ),
-//         cv_core::Point2i::new(
-//             (bbox2d.left + bbox2d.width / 2.) as i32,
-//             (bbox2d.top + bbox2d.height / 2.) as i32,
-//         ),
-//         imgproc::FONT_HERSHEY_SIMPLEX,
-//         0.5,
-//         cv_core::Scalar::new(255.0, 191.0, 0.0, 0.0),
-//         1,
-//         imgproc::LINE_8,
-//         false,
-//     )
-//     .unwrap();
-// }
-
-// pub fn put_text(image: &mut Mat, text: &str, org: cv_core::Point) {
-//     imgproc::put_text(
-//         image,
-//         text,
-//         org,
-//         imgproc::FONT_HERSHEY_SIMPLEX,
-//         1.0,
-//         cv_core::Scalar::new(255.0, 191.0, 0.0, 0.0),
-//         2,
-//         imgproc::LINE_8,
-//         false,
-//     )
-//     .unwrap();
-// }
-
-// pub fn draw_objects(
-//     image: &mut Mat,
-//     objects: &Vec<KittiObject>,
-//     calib: &KittiCalib,
-//     mode: &DrawingMode,
-// ) {
-//     for obj in objects {
-//         match mode {
-//             DrawingMode::Draw2D => {
-//                 draw_2d_bbox(&obj, image);
-//             }
-//             DrawingMode::Draw3D => {
-//                 let bbox = obj.bbox3d.clone();
-//                 let vertices = bbox.vertices();
-//                 let vertices_2d = project_3d_points(&vertices, calib);
-//                 draw_bbox3d_edges(vertices_2d, image);
-//             }
-//         }
-//     }
-// }
-
-// pub fn project_3d_points(
-//     points3d: &Vec<na::Point3<f64>>,
-//     calib: &KittiCalib,
-// ) -> Vec<cv_core::Point2i> {
-//     let mut points2d: Vec<cv_core::Point2i> = vec![];
-//     let rect2velo = calib.get_transformation_from_rectified_camera_to_velodyne();
-//     points3d.iter().for_each(|point3d| {
-//         let rect_point = rect2velo.inverse() * point3d;
-//         let rect_point_homo =
-//             DMatrix::from_row_slice(4, 1, &[rect_point.x, rect_point.y, rect_point.z, 1.0]);
-//         let camera_point = calib.p0.clone() * rect_point_homo;
-//         let z = camera_point[(2, 0)];
-//         let x = (camera_point[(0, 0)] / z) as i32;
-//         let y = (camera_point[(1, 0)] / z) as i32;
-//         points2d.push(cv_core::Point2i::new(x, y));
-//     });
-//     points2d
-// }
-
-// pub fn draw_bbox3d_edges(vertices: Vec<cv_core::Point2i>, image: &mut cv_core::Mat) {
-//     let edge_relation = vec![
-//         (0, 1),
-//         (0, 2),
-//         (1, 3),
-//         (2, 3),
-//         (4, 5),
-//         (4, 6),
-//         (5, 7),
-//         (6, 7),
-//         (0, 4),
-//         (1, 5),
-//         (2, 6),
-//         (3, 7),
-//         (1, 7),
-//         (3, 5),
-//     ];
-//     edge_relation.into_iter().for_each(|(from_idx, to_idx)| {
-//         imgproc::line(
-//             image,
-//             vertices[from_idx],
-//             vertices[to_idx],
-//             cv_core::Scalar::new(0.0, 255.0, 0.0, 0.0),
-//             1,
-//             8,
-//             0,
-//         )
-//         .unwrap();
-//     });
-// }
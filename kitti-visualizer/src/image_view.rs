@@ -0,0 +1,211 @@
+use crate::{point_cloud::PointCloud, utils::BOX_EDGES};
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use image::{Rgb, RgbImage};
+use kitti_format::{ImageProjection, KittiObject, Storage};
+use nalgebra as na;
+use rayon::prelude::*;
+use scarlet::{
+    color::RGBColor,
+    colormap::{ColorMap, ListedColorMap},
+};
+use std::path::Path;
+
+const BOX_2D_COLOR: Rgb<u8> = Rgb([0, 191, 255]);
+const BOX_3D_COLOR: Rgb<u8> = Rgb([0, 255, 0]);
+/// Points at this depth or farther get the last colour of the map.
+const MAX_DEPTH: f64 = 80.0;
+
+/// What the points projected onto an image are coloured by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum OverlayColor {
+    Depth,
+    Intensity,
+}
+
+pub fn load_image(storage: &dyn Storage, path: &Path) -> Result<RgbImage> {
+    let bytes = storage
+        .read(path)
+        .with_context(|| format!("unable to read {}", path.display()))?;
+    let image = image::load_from_memory(&bytes)
+        .with_context(|| format!("unable to decode {}", path.display()))?;
+    Ok(image.to_rgb8())
+}
+
+/// Draws the 2D box of each object and the wireframe of its 3D box
+/// projected onto the image.
+pub fn draw_objects(image: &mut RgbImage, objects: &[KittiObject], projection: &ImageProjection) {
+    for obj in objects {
+        let [t, l, b, r] = obj.bbox2d.tlbr();
+        if b > t && r > l {
+            draw_rect(
+                image,
+                na::Point2::new(l, t),
+                na::Point2::new(r, b),
+                BOX_2D_COLOR,
+            );
+        }
+
+        let vertices: Vec<_> = obj
+            .bbox3d
+            .vertices()
+            .iter()
+            .map(|vertex| projection.project(vertex).map(|(pixel, _)| pixel))
+            .collect();
+        for &(from_idx, to_idx) in BOX_EDGES {
+            if let (Some(from), Some(to)) = (vertices[from_idx], vertices[to_idx]) {
+                draw_line(image, from, to, BOX_3D_COLOR);
+            }
+        }
+    }
+}
+
+/// Draws the points of a cloud that project into the image. Far points
+/// are drawn first so that near ones stay on top.
+pub fn draw_points(
+    image: &mut RgbImage,
+    cloud: &PointCloud,
+    projection: &ImageProjection,
+    color_by: OverlayColor,
+    color_map: &ListedColorMap,
+) {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let mut projected: Vec<_> = cloud
+        .points()
+        .par_iter()
+        .filter_map(|&[x, y, z, intensity]| {
+            let (pixel, depth) = projection.project(&na::Point3::new(x, y, z).cast())?;
+            let inside = (0.0..width).contains(&pixel.x) && (0.0..height).contains(&pixel.y);
+            inside.then_some((pixel, depth, intensity as f64))
+        })
+        .collect();
+    projected.par_sort_by(|(_, lhs, _), (_, rhs, _)| rhs.total_cmp(lhs));
+
+    let (min_intensity, max_intensity) = projected
+        .iter()
+        .map(|&(_, _, intensity)| intensity)
+        .fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), value| {
+            (min.min(value), max.max(value))
+        });
+
+    for (pixel, depth, intensity) in projected {
+        let scaled = match color_by {
+            OverlayColor::Depth => (depth / MAX_DEPTH).min(1.0),
+            OverlayColor::Intensity if max_intensity > min_intensity => {
+                (intensity - min_intensity) / (max_intensity - min_intensity)
+            }
+            OverlayColor::Intensity => 0.0,
+        };
+        let color: RGBColor = color_map.transform_single(scaled);
+        let color = [color.r, color.g, color.b].map(|c| (c * 255.0).round() as u8);
+        draw_dot(image, pixel, 1, Rgb(color));
+    }
+}
+
+/// Looks up the pixel each point of a cloud projects to, giving `None`
+/// for points outside the image.
+pub fn sample_colors(
+    image: &RgbImage,
+    cloud: &PointCloud,
+    projection: &ImageProjection,
+) -> Vec<Option<[u8; 3]>> {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    cloud
+        .points()
+        .par_iter()
+        .map(|&[x, y, z, _]| {
+            let (pixel, _) = projection.project(&na::Point3::new(x, y, z).cast())?;
+            let inside = (0.0..width).contains(&pixel.x) && (0.0..height).contains(&pixel.y);
+            inside.then(|| image.get_pixel(pixel.x as u32, pixel.y as u32).0)
+        })
+        .collect()
+}
+
+/// Places two images next to each other.
+pub fn side_by_side(left: &RgbImage, right: &RgbImage) -> RgbImage {
+    let height = left.height().max(right.height());
+    RgbImage::from_fn(left.width() + right.width(), height, |x, y| {
+        let (image, x) = if x < left.width() {
+            (left, x)
+        } else {
+            (right, x - left.width())
+        };
+        if y < image.height() {
+            *image.get_pixel(x, y)
+        } else {
+            Rgb([0, 0, 0])
+        }
+    })
+}
+
+pub fn draw_rect(
+    image: &mut RgbImage,
+    top_left: na::Point2<f64>,
+    bottom_right: na::Point2<f64>,
+    color: Rgb<u8>,
+) {
+    let top_right = na::Point2::new(bottom_right.x, top_left.y);
+    let bottom_left = na::Point2::new(top_left.x, bottom_right.y);
+    draw_line(image, top_left, top_right, color);
+    draw_line(image, top_right, bottom_right, color);
+    draw_line(image, bottom_right, bottom_left, color);
+    draw_line(image, bottom_left, top_left, color);
+}
+
+/// Draws a line three pixels wide, clipped to the image.
+pub fn draw_line(image: &mut RgbImage, from: na::Point2<f64>, to: na::Point2<f64>, color: Rgb<u8>) {
+    let size = na::Vector2::new(image.width() as f64, image.height() as f64);
+    let Some((from, to)) = clip_line(from, to, size) else {
+        return;
+    };
+    let num_steps = (to - from).amax().ceil() as usize;
+    for step in 0..=num_steps {
+        let ratio = if num_steps == 0 {
+            0.0
+        } else {
+            step as f64 / num_steps as f64
+        };
+        draw_dot(image, from + (to - from) * ratio, 1, color);
+    }
+}
+
+/// Fills the square of pixels within `radius` of a point.
+pub fn draw_dot(image: &mut RgbImage, center: na::Point2<f64>, radius: i64, color: Rgb<u8>) {
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let (cx, cy) = (center.x.floor() as i64, center.y.floor() as i64);
+    for y in (cy - radius).max(0)..=(cy + radius).min(height - 1) {
+        for x in (cx - radius).max(0)..=(cx + radius).min(width - 1) {
+            image.put_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+/// Clips a line to the rectangle from the origin to `size` with the
+/// Liang-Barsky algorithm.
+fn clip_line(
+    from: na::Point2<f64>,
+    to: na::Point2<f64>,
+    size: na::Vector2<f64>,
+) -> Option<(na::Point2<f64>, na::Point2<f64>)> {
+    let delta = to - from;
+    let mut t_min: f64 = 0.0;
+    let mut t_max: f64 = 1.0;
+    let bounds = [
+        (-delta.x, from.x),
+        (delta.x, size.x - from.x),
+        (-delta.y, from.y),
+        (delta.y, size.y - from.y),
+    ];
+    for (p, q) in bounds {
+        if p == 0.0 {
+            if q < 0.0 {
+                return None;
+            }
+        } else if p < 0.0 {
+            t_min = t_min.max(q / p);
+        } else {
+            t_max = t_max.min(q / p);
+        }
+    }
+    (t_min <= t_max).then(|| (from + delta * t_min, from + delta * t_max))
+}
@@ -1,23 +1,110 @@
+mod calib_check;
+mod calib_score;
+mod compress;
+mod convert;
 mod gui;
+mod image_view;
+mod overlay;
+mod point_cloud;
+mod read_las;
 mod read_pcd;
+mod read_ply;
+mod sparse_depth;
 mod utils;
+mod write_pcd;
 
-use crate::gui::Gui;
+use crate::{
+    calib_check::CalibCheckOpts,
+    compress::{CompressOpts, DecompressOpts},
+    convert::ConvertOpts,
+    gui::{Gui, GuiConfig},
+    overlay::OverlayOpts,
+    read_pcd::CloudFormat,
+    sparse_depth::SparseDepthOpts,
+    utils::{BoxMargins, ClassMargin, CloudConfig, Margin},
+};
 use anyhow::Result;
-use clap::{Parser, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use kiss3d::window::Window;
+use kitti_format::KittiSource;
 use std::{fs, path::PathBuf};
 
 #[derive(Parser)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Opts {
-    #[clap(short, long)]
-    pub kitti_dir: PathBuf,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+    #[clap(flatten)]
+    pub view: ViewOpts,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Compress the scans in a velodyne directory.
+    Compress(CompressOpts),
+    /// Decompress the scans in a velodyne directory.
+    Decompress(DecompressOpts),
+    /// Convert the point clouds of a dataset between bin, PCD and PLY.
+    Convert(ConvertOpts),
+    /// Draw the scans projected onto the camera images.
+    Overlay(OverlayOpts),
+    /// Write the scans as sparse depth maps of the KITTI depth benchmark.
+    SparseDepth(SparseDepthOpts),
+    /// Score the lidar-camera alignment of every frame and flag outliers.
+    CalibCheck(CalibCheckOpts),
+}
+
+#[derive(Args)]
+struct ViewOpts {
+    /// Dataset directory or KITTI zip archive. Repeat to combine several
+    /// archives, e.g. velodyne, label_2 and calib.
+    #[clap(short, long, required = true)]
+    pub kitti_dir: Vec<PathBuf>,
+    /// The split directory inside zip archives.
+    #[clap(long, default_value = "training")]
+    pub split: String,
     #[clap(short, long)]
     pub supervisely_ann_dir: Option<PathBuf>,
     #[clap(short, long)]
     pub screencast_dir: Option<PathBuf>,
     #[clap(short, long, default_value = "libpcl")]
     pub format: PcdFormat,
+    /// The file format of the point clouds in the velodyne directory.
+    #[clap(long, default_value = "bin")]
+    pub cloud_format: CloudFormat,
+    /// A directory of scans from another LiDAR, e.g. `velodyne_left`.
+    /// Repeat for each extra LiDAR.
+    #[clap(long)]
+    pub lidar: Vec<String>,
+    /// A directory inside the dataset, like `velodyne`, of `<index>.bin`
+    /// masks with one byte per point of the velodyne scan, where `0`
+    /// marks an inactive point.
+    #[clap(long)]
+    pub mask_dir: Option<PathBuf>,
+    /// A directory of `<index>.png` disparity maps of the left colour
+    /// camera in the 16-bit KITTI format, shown as a pseudo-LiDAR cloud.
+    #[clap(long)]
+    pub disparity_dir: Option<PathBuf>,
+    /// A directory of `<index>.png` depth maps of the left colour camera
+    /// in the 16-bit KITTI format, e.g. predicted dense depth.
+    #[clap(long)]
+    pub depth_dir: Option<PathBuf>,
+    /// Where the calibration tuned in the viewer is saved as
+    /// `<index>.txt`.
+    #[clap(long, default_value = "calib_tuned")]
+    pub calib_output_dir: PathBuf,
+    /// Boxes with fewer points inside are drawn in magenta.
+    #[clap(long, default_value = "5")]
+    pub min_box_points: usize,
+    /// How far outside a box points still count as inside it, in metres
+    /// along its length, width and height. Give one value for all three
+    /// or `<x>,<y>,<z>`.
+    #[clap(long, default_value = "0.1")]
+    pub box_margin: Margin,
+    /// The margin of one class, e.g. `Pedestrian=0.3,0.3,0.1`. Repeat for
+    /// each class that needs its own.
+    #[clap(long)]
+    pub class_margin: Vec<ClassMargin>,
     #[clap(long)]
     pub play_on_start: bool,
     #[clap(long)]
@@ -31,14 +118,43 @@ pub enum PcdFormat {
 }
 
 fn main() -> Result<()> {
-    let Opts {
+    let Opts { command, view } = Opts::parse();
+
+    match command {
+        Some(Command::Compress(opts)) => compress::compress_dir(&opts),
+        Some(Command::Decompress(opts)) => compress::decompress_dir(&opts),
+        Some(Command::Convert(opts)) => convert::convert_dataset(&opts),
+        Some(Command::Overlay(opts)) => overlay::export_overlays(&opts),
+        Some(Command::SparseDepth(opts)) => sparse_depth::export_sparse_depth(&opts),
+        Some(Command::CalibCheck(opts)) => calib_check::check_calibration(&opts),
+        None => run_viewer(view),
+    }
+}
+
+fn run_viewer(opts: ViewOpts) -> Result<()> {
+    let ViewOpts {
         kitti_dir,
+        split,
         supervisely_ann_dir,
         format,
+        cloud_format,
+        lidar,
+        mask_dir,
+        disparity_dir,
+        depth_dir,
+        calib_output_dir,
+        min_box_points,
+        box_margin,
+        class_margin,
         play_on_start,
         record_on_start,
         screencast_dir,
-    } = Opts::parse();
+    } = opts;
+
+    let source = KittiSource::new(&kitti_dir, &split)?;
+    let supervisely_source = supervisely_ann_dir
+        .map(|dir| KittiSource::new(&[dir], &split))
+        .transpose()?;
 
     if let Some(screencast_dir) = &screencast_dir {
         fs::create_dir_all(screencast_dir)?;
@@ -48,96 +164,24 @@ fn main() -> Result<()> {
     window.set_background_color(1., 1., 1.);
     window.set_line_width(2.);
 
-    let gui = Gui::new(
-        kitti_dir,
-        supervisely_ann_dir,
+    let config = GuiConfig {
         screencast_dir,
-        format,
+        pcd_format: format,
+        cloud: CloudConfig {
+            format: cloud_format,
+            lidars: lidar,
+            mask_dir,
+            disparity_dir,
+            depth_dir,
+        },
+        calib_output_dir,
+        min_box_points,
+        box_margins: BoxMargins::new(box_margin, &class_margin),
         play_on_start,
         record_on_start,
-    )?;
+    };
+    let gui = Gui::new(source, supervisely_source, config)?;
     window.render_loop(gui);
 
     Ok(())
 }
-
-// fn visualize_in_image(kitti_dir: PathBuf) {
-//     let exclude_classes = vec!["DontCare".into()];
-//     let image_dir = kitti_dir.join("image_2");
-//     let calib_dir = kitti_dir.join("calib");
-//     let ann_dir = kitti_dir.join("label_2");
-//     let indices = get_indices_from_ann_dir(&ann_dir);
-//     let mut idx = 0;
-//     let mut index: Option<i32> = Some(indices[idx]);
-//     let mut mode = DrawingMode::Draw3D;
-
-//     loop {
-//         if index.is_none() {
-//             eprint!("Enter the dataset index: ");
-//             index = Some(read!());
-//             idx = indices.iter().position(|&i| Some(i) == index).unwrap();
-//         }
-//         // Get the calib file
-//         let calib_path = calib_dir.join(format!("{:0>6}.txt", index.unwrap().to_string()));
-//         let calib = KittiCalib::from_file(calib_path);
-//         // Get the annotation file
-//         let ann_path = ann_dir.join(format!("{:0>6}.txt", index.unwrap().to_string()));
-//         let objects = kitti_format::read_ann_file(ann_path, &calib, &exclude_classes);
-//         let image_path = image_dir.join(format!("{:0>6}.png", index.unwrap().to_string()));
-//         let mut image = imread(image_path.to_str().unwrap(), 1).unwrap();
-
-//         // Draw bboxes
-//         draw_objects(&mut image, &objects, &calib, &mode);
-//         put_text(
-//             &mut image,
-//             &format!(
-//                 "frame index: {}",
-//                 format!("{:0>6}", index.unwrap().to_string())
-//             ),
-//             cv_core::Point::new(0, 30),
-//         );
-//         imshow("image", &image).unwrap();
-//         loop {
-//             let key = wait_key(0).unwrap();
-//             if key == 27 {
-//                 index = None;
-//                 break;
-//             }
-//             if key == 81 {
-//                 if idx == 0 {
-//                     idx = indices.len() - 1;
-//                 } else {
-//                     idx = idx - 1;
-//                 }
-
-//                 index = Some(indices[idx]);
-//                 break;
-//             }
-//             if key == 83 {
-//                 idx = idx + 1;
-//                 if idx >= indices.len() {
-//                     idx = 0;
-//                 }
-//                 index = Some(indices[idx]);
-//                 break;
-//             }
-//             if key == 115 {
-//                 match mode {
-//                     DrawingMode::Draw3D => {
-//                         mode = DrawingMode::Draw2D;
-//                     }
-//                     DrawingMode::Draw2D => {
-//                         mode = DrawingMode::Draw3D;
-//                     }
-//                 }
-//                 break;
-//             }
-//             // if key == 114 {
-//             //     objects
-//             //         .iter_mut()
-//             //         .for_each(|obj| (*obj).bbox3d.rot_y += 0.1);
-//             //     break;
-//             // }
-//         }
-//     }
-// }
@@ -0,0 +1,78 @@
+use crate::{
+    compress::write_atomic,
+    image_view::{draw_objects, draw_points, OverlayColor},
+    utils::{
+        export_each_frame, get_objects_from_frame_id, list_image_indices, load_camera_frame,
+        load_frame_cloud, CloudConfig, ImageDatasetOpts,
+    },
+    PcdFormat,
+};
+use anyhow::{Context, Result};
+use clap::Args;
+use image::ImageOutputFormat;
+use kitti_format::{ImageProjection, KittiCamera, KittiSource};
+use scarlet::colormap::ListedColorMap;
+use std::{fs, io::Cursor, path::PathBuf};
+
+#[derive(Debug, Clone, Args)]
+pub struct OverlayOpts {
+    #[clap(flatten)]
+    pub dataset: ImageDatasetOpts,
+    /// Directory to write the `<index>.png` overlays to.
+    pub output_dir: PathBuf,
+    /// What to colour the points by.
+    #[clap(long, default_value = "depth")]
+    pub color_by: OverlayColor,
+    /// Also draw the boxes of label_2.
+    #[clap(long)]
+    pub boxes: bool,
+}
+
+/// Projects the scan of every frame with an `image_2` picture onto it,
+/// to check the calibration without opening the viewer. Frames that
+/// fail are skipped and reported at the end.
+pub fn export_overlays(opts: &OverlayOpts) -> Result<()> {
+    let OverlayOpts {
+        dataset:
+            ImageDatasetOpts {
+                ref input,
+                ref split,
+                cloud_format,
+            },
+        ref output_dir,
+        color_by,
+        boxes,
+    } = *opts;
+
+    let source = KittiSource::new(input, split)?;
+    let indices = list_image_indices(&source)?;
+
+    fs::create_dir_all(output_dir)?;
+    let cloud_config = CloudConfig::scan_only(cloud_format);
+    let color_map = ListedColorMap::plasma();
+
+    export_each_frame(&indices, |index| {
+        let camera = load_camera_frame(index, &source, &cloud_config)?
+            .with_context(|| format!("no calibration for frame {index}"))?;
+        let cloud = load_frame_cloud(index, &source, &cloud_config)?;
+        let projection = ImageProjection::new(&camera.calib, KittiCamera::Cam2);
+
+        let mut image = camera.image;
+        draw_points(&mut image, &cloud, &projection, color_by, &color_map);
+        if boxes {
+            let objects = get_objects_from_frame_id(index, &source, None, PcdFormat::Libpcl)?;
+            draw_objects(&mut image, &objects, &projection);
+        }
+
+        let mut data = Cursor::new(vec![]);
+        image.write_to(&mut data, ImageOutputFormat::Png)?;
+        write_atomic(&output_dir.join(format!("{index:0>6}.png")), data.get_ref())
+    })?;
+
+    eprintln!(
+        "wrote {} overlays into {}",
+        indices.len(),
+        output_dir.display()
+    );
+    Ok(())
+}
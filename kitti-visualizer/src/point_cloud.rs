@@ -0,0 +1,306 @@
+use crate::read_pcd::VelodyneScan;
+use anyhow::{ensure, Result};
+use nalgebra as na;
+use std::{collections::BTreeMap, io::prelude::*};
+
+/// The channel name of the intensity, which is stored together with
+/// the position.
+pub const INTENSITY: &str = "intensity";
+
+/// The channel name of the index of the LiDAR that captured each point
+/// in a multi-LiDAR frame.
+pub const DEVICE_ID: &str = "device_id";
+
+/// The channel name of the per-point mask, where `0` marks an inactive
+/// point and any other value an active one.
+pub const ACTIVE: &str = "active";
+
+/// The channel names of SemanticKITTI class and instance ids.
+pub const SEMANTIC: &str = "semantic";
+pub const INSTANCE: &str = "instance";
+
+/// The value type of a [Channel].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelKind {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl ChannelKind {
+    /// The size of one value in bytes.
+    pub fn size(&self) -> usize {
+        match self {
+            ChannelKind::I8 | ChannelKind::U8 => 1,
+            ChannelKind::I16 | ChannelKind::U16 => 2,
+            ChannelKind::I32 | ChannelKind::U32 | ChannelKind::F32 => 4,
+            ChannelKind::F64 => 8,
+        }
+    }
+}
+
+/// A typed column of per-point values.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Channel {
+    I8(Vec<i8>),
+    U8(Vec<u8>),
+    I16(Vec<i16>),
+    U16(Vec<u16>),
+    I32(Vec<i32>),
+    U32(Vec<u32>),
+    F32(Vec<f32>),
+    F64(Vec<f64>),
+}
+
+macro_rules! with_values {
+    ($channel:expr, $values:ident => $body:expr) => {
+        match $channel {
+            Channel::I8($values) => $body,
+            Channel::U8($values) => $body,
+            Channel::I16($values) => $body,
+            Channel::U16($values) => $body,
+            Channel::I32($values) => $body,
+            Channel::U32($values) => $body,
+            Channel::F32($values) => $body,
+            Channel::F64($values) => $body,
+        }
+    };
+}
+
+impl Channel {
+    pub fn new(kind: ChannelKind) -> Self {
+        match kind {
+            ChannelKind::I8 => Channel::I8(vec![]),
+            ChannelKind::U8 => Channel::U8(vec![]),
+            ChannelKind::I16 => Channel::I16(vec![]),
+            ChannelKind::U16 => Channel::U16(vec![]),
+            ChannelKind::I32 => Channel::I32(vec![]),
+            ChannelKind::U32 => Channel::U32(vec![]),
+            ChannelKind::F32 => Channel::F32(vec![]),
+            ChannelKind::F64 => Channel::F64(vec![]),
+        }
+    }
+
+    pub fn kind(&self) -> ChannelKind {
+        match self {
+            Channel::I8(_) => ChannelKind::I8,
+            Channel::U8(_) => ChannelKind::U8,
+            Channel::I16(_) => ChannelKind::I16,
+            Channel::U16(_) => ChannelKind::U16,
+            Channel::I32(_) => ChannelKind::I32,
+            Channel::U32(_) => ChannelKind::U32,
+            Channel::F32(_) => ChannelKind::F32,
+            Channel::F64(_) => ChannelKind::F64,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        with_values!(self, values => values.len())
+    }
+
+    pub fn get(&self, idx: usize) -> f64 {
+        match self {
+            Channel::I8(values) => values[idx].into(),
+            Channel::U8(values) => values[idx].into(),
+            Channel::I16(values) => values[idx].into(),
+            Channel::U16(values) => values[idx].into(),
+            Channel::I32(values) => values[idx].into(),
+            Channel::U32(values) => values[idx].into(),
+            Channel::F32(values) => values[idx].into(),
+            Channel::F64(values) => values[idx],
+        }
+    }
+
+    /// Appends a value, converting it to the channel's type with `as`.
+    pub fn push(&mut self, value: f64) {
+        with_values!(self, values => values.push(value as _))
+    }
+
+    /// Appends all values of another channel, converting them to this
+    /// channel's type.
+    pub fn extend_from(&mut self, other: &Channel) {
+        for idx in 0..other.len() {
+            self.push(other.get(idx));
+        }
+    }
+
+    /// Writes the value at `idx` in little-endian byte order.
+    pub fn write_le<W: Write>(&self, idx: usize, mut writer: W) -> std::io::Result<()> {
+        with_values!(self, values => writer.write_all(&values[idx].to_le_bytes()))
+    }
+
+    /// Writes the value at `idx` as text without losing precision.
+    pub fn write_ascii<W: Write>(&self, idx: usize, mut writer: W) -> std::io::Result<()> {
+        with_values!(self, values => write!(writer, "{}", values[idx]))
+    }
+
+    /// Returns the smallest and largest finite values.
+    pub fn range(&self) -> Option<(f64, f64)> {
+        (0..self.len())
+            .map(|idx| self.get(idx))
+            .filter(|value| value.is_finite())
+            .fold(None, |range, value| match range {
+                None => Some((value, value)),
+                Some((min, max)) => Some((min.min(value), max.max(value))),
+            })
+    }
+}
+
+/// A point cloud stored column by column.
+///
+/// Positions and intensities are kept as the `[x, y, z, intensity]`
+/// records of a [VelodyneScan], so `.bin` scans stay memory-mapped.
+/// Every other attribute, e.g. `ring`, `time`, `label` or the `r`, `g`
+/// and `b` colour channels, is a named [Channel] with its own type.
+pub struct PointCloud {
+    scan: VelodyneScan,
+    channels: BTreeMap<String, Channel>,
+}
+
+impl PointCloud {
+    pub fn from_scan(scan: VelodyneScan) -> Self {
+        Self {
+            scan,
+            channels: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a cloud from positions and intensities. Use `0.0` for
+    /// formats without an intensity.
+    pub fn from_points(points: Vec<[f32; 4]>) -> Self {
+        Self::from_scan(VelodyneScan::from_points(points))
+    }
+
+    /// Concatenates the clouds of several LiDARs, which must already be
+    /// in a common frame, and stores the index of the source cloud of
+    /// each point in the [DEVICE_ID] channel. Channels missing from some
+    /// of the clouds are dropped.
+    pub fn merge_devices(clouds: Vec<PointCloud>) -> Result<Self> {
+        ensure!(
+            clouds.len() <= u8::MAX as usize + 1,
+            "at most {} devices are supported",
+            u8::MAX as usize + 1
+        );
+
+        let mut points = vec![];
+        let mut device_ids = vec![];
+        for (device_id, cloud) in clouds.iter().enumerate() {
+            points.extend_from_slice(cloud.points());
+            device_ids.extend(std::iter::repeat_n(device_id as u8, cloud.len()));
+        }
+        let mut merged = Self::from_points(points);
+
+        let Some(first) = clouds.first() else {
+            return Ok(merged);
+        };
+        for (name, channel) in first.channels() {
+            if name == DEVICE_ID || !clouds.iter().all(|cloud| cloud.channel(name).is_some()) {
+                continue;
+            }
+            let mut merged_channel = Channel::new(channel.kind());
+            for cloud in &clouds {
+                merged_channel.extend_from(cloud.channel(name).unwrap());
+            }
+            merged.insert_channel(name, merged_channel)?;
+        }
+        merged.insert_channel(DEVICE_ID, Channel::U8(device_ids))?;
+
+        Ok(merged)
+    }
+
+    /// Moves the points into another frame. The result owns its points
+    /// even if the input was memory-mapped.
+    pub fn transform(self, transform: &na::Isometry3<f32>) -> Self {
+        let points = self
+            .points()
+            .iter()
+            .map(|&[x, y, z, intensity]| {
+                let point = transform * na::Point3::new(x, y, z);
+                [point.x, point.y, point.z, intensity]
+            })
+            .collect();
+        Self {
+            scan: VelodyneScan::from_points(points),
+            channels: self.channels,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scan.len()
+    }
+
+    /// The `[x, y, z, intensity]` records.
+    pub fn points(&self) -> &[[f32; 4]] {
+        self.scan.points()
+    }
+
+    pub fn position(&self, idx: usize) -> na::Point3<f32> {
+        let [x, y, z, _] = self.points()[idx];
+        [x, y, z].into()
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels.get(name)
+    }
+
+    pub fn channels(&self) -> impl Iterator<Item = (&str, &Channel)> {
+        self.channels
+            .iter()
+            .map(|(name, channel)| (name.as_str(), channel))
+    }
+
+    /// Adds or replaces a channel. It must have one value per point.
+    pub fn insert_channel(&mut self, name: impl Into<String>, channel: Channel) -> Result<()> {
+        let name = name.into();
+        ensure!(
+            name != INTENSITY,
+            "{INTENSITY} is stored with the positions"
+        );
+        ensure!(
+            channel.len() == self.len(),
+            "channel {name} has {} values for {} points",
+            channel.len(),
+            self.len()
+        );
+        self.channels.insert(name, channel);
+        Ok(())
+    }
+
+    /// Whether [PointCloud::value] knows the attribute.
+    pub fn has_attribute(&self, name: &str) -> bool {
+        name == INTENSITY || self.channels.contains_key(name)
+    }
+
+    /// Looks up a value by channel name, including [INTENSITY].
+    pub fn value(&self, name: &str, idx: usize) -> Option<f64> {
+        if name == INTENSITY {
+            return Some(self.points()[idx][3] as f64);
+        }
+        Some(self.channel(name)?.get(idx))
+    }
+
+    /// The names usable with [PointCloud::value], starting with
+    /// [INTENSITY].
+    pub fn attribute_names(&self) -> Vec<String> {
+        std::iter::once(INTENSITY.to_string())
+            .chain(self.channels.keys().cloned())
+            .collect()
+    }
+
+    /// Whether a point is active. Points are active unless an [ACTIVE]
+    /// channel says otherwise.
+    pub fn is_active(&self, idx: usize) -> bool {
+        self.channel(ACTIVE)
+            .is_none_or(|active| active.get(idx) != 0.0)
+    }
+
+    /// Returns the `r`, `g` and `b` channels if all of them exist.
+    pub fn rgb(&self) -> Option<[&Channel; 3]> {
+        Some([self.channel("r")?, self.channel("g")?, self.channel("b")?])
+    }
+}
@@ -0,0 +1,68 @@
+use crate::{
+    point_cloud::{Channel, PointCloud},
+    read_pcd::read_decoded,
+};
+use anyhow::{Context, Result};
+use kitti_format::Storage;
+use las::Read as _;
+use std::{io::Cursor, path::Path};
+
+/// Loads a LAS or LAZ file. Coordinates and intensity fill the
+/// positions. `classification`, `return_number` and, if present, `time`
+/// (GPS time) and the `r`, `g` and `b` colour channels are stored as
+/// channels. LAS colours are scaled from 16 to 8 bits, unless no value
+/// exceeds 255, as many writers store 8-bit colours in the 16-bit fields.
+pub fn load_las(storage: &dyn Storage, las_path: &Path) -> Result<PointCloud> {
+    let bytes = read_decoded(storage, las_path)?;
+    let mut reader = las::Reader::new(Cursor::new(bytes))
+        .with_context(|| format!("Failed to parse LAS header of {}", las_path.display()))?;
+
+    let mut points = vec![];
+    let mut classification = vec![];
+    let mut return_number = vec![];
+    let mut time = vec![];
+    let mut rgb: [Vec<u16>; 3] = Default::default();
+
+    for point in reader.points() {
+        let point = point?;
+
+        points.push([
+            point.x as f32,
+            point.y as f32,
+            point.z as f32,
+            point.intensity as f32,
+        ]);
+        classification.push(u8::from(point.classification));
+        return_number.push(point.return_number);
+
+        if let Some(gps_time) = point.gps_time {
+            time.push(gps_time);
+        }
+        if let Some(color) = point.color {
+            let [r, g, b] = &mut rgb;
+            r.push(color.red);
+            g.push(color.green);
+            b.push(color.blue);
+        }
+    }
+
+    let mut cloud = PointCloud::from_points(points);
+    cloud.insert_channel("classification", Channel::U8(classification))?;
+    cloud.insert_channel("return_number", Channel::U8(return_number))?;
+    if time.len() == cloud.len() {
+        cloud.insert_channel("time", Channel::F64(time))?;
+    }
+    let shift = if rgb.iter().flatten().any(|&value| value > 255) {
+        8
+    } else {
+        0
+    };
+    for (name, values) in ["r", "g", "b"].into_iter().zip(rgb) {
+        if values.len() == cloud.len() {
+            let values = values.iter().map(|&value| (value >> shift) as u8).collect();
+            cloud.insert_channel(name, Channel::U8(values))?;
+        }
+    }
+
+    Ok(cloud)
+}
@@ -1,66 +1,502 @@
-use anyhow::{Context, Result};
-use byteorder::{LittleEndian, ReadBytesExt};
-use nalgebra as na;
+use crate::{
+    compress::Codec,
+    point_cloud::{Channel, ChannelKind, PointCloud},
+    read_las::load_las,
+    read_ply::load_ply,
+};
+use anyhow::{bail, ensure, Context, Result};
+use clap::ValueEnum;
+use kitti_format::Storage;
+use memmap2::Mmap;
+use pcd_rs::{DynReader, DynRecord, Field};
 use std::{
+    collections::BTreeMap,
     fs::File,
-    io::{self, prelude::*, BufReader},
+    io::{prelude::*, Cursor},
+    mem,
     path::Path,
 };
 
-#[derive(Clone, Debug)]
-pub struct InfoPoint {
-    pub point: na::Point3<f32>,
-    pub intensity: f32,
-    pub device_id: Option<u64>,
-    pub active: Option<u64>,
+/// The file format of the point clouds in the `velodyne` directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum CloudFormat {
+    /// KITTI `.bin` scans.
+    Bin,
+    /// PCL `.pcd` files.
+    Pcd,
+    /// `.ply` files.
+    Ply,
+    /// ASPRS `.las` files.
+    Las,
+    /// Compressed ASPRS `.laz` files.
+    Laz,
+}
+
+impl CloudFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            CloudFormat::Bin => "bin",
+            CloudFormat::Pcd => "pcd",
+            CloudFormat::Ply => "ply",
+            CloudFormat::Las => "las",
+            CloudFormat::Laz => "laz",
+        }
+    }
+}
+
+/// A KITTI velodyne scan viewed as `[x, y, z, intensity]` records.
+///
+/// Uncompressed scans on the local file system are memory-mapped and
+/// never copied. Compressed or archived scans are decoded into memory.
+pub struct VelodyneScan {
+    data: ScanData,
+}
+
+enum ScanData {
+    Mapped(Mmap),
+    Owned(Vec<[f32; 4]>),
+}
+
+impl VelodyneScan {
+    pub fn open(storage: &dyn Storage, pcd_path: &Path) -> Result<Self> {
+        let local_path = storage
+            .local_path(pcd_path)
+            .filter(|_| Codec::from_path(pcd_path).is_none() && cfg!(target_endian = "little"));
+
+        if let Some(local_path) = local_path {
+            let file = File::open(&local_path)
+                .with_context(|| format!("Failed to open file {}", local_path.display()))?;
+            // SAFETY: The mapping is read-only. Scans are not expected to
+            // be modified while the viewer is running.
+            let mmap = unsafe { Mmap::map(&file)? };
+            check_scan_len(pcd_path, mmap.len())?;
+            return Ok(Self {
+                data: ScanData::Mapped(mmap),
+            });
+        }
+
+        let bytes = read_decoded(storage, pcd_path)?;
+        check_scan_len(pcd_path, bytes.len())?;
+
+        let points = bytes
+            .chunks_exact(mem::size_of::<[f32; 4]>())
+            .map(|chunk| {
+                let value = |idx: usize| {
+                    f32::from_le_bytes(chunk[idx * 4..(idx + 1) * 4].try_into().unwrap())
+                };
+                [value(0), value(1), value(2), value(3)]
+            })
+            .collect();
+
+        Ok(Self::from_points(points))
+    }
+
+    pub fn from_points(points: Vec<[f32; 4]>) -> Self {
+        Self {
+            data: ScanData::Owned(points),
+        }
+    }
+
+    pub fn points(&self) -> &[[f32; 4]] {
+        match &self.data {
+            ScanData::Mapped(mmap) => bytemuck::cast_slice(mmap),
+            ScanData::Owned(points) => points,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.points().len()
+    }
 }
 
-pub fn load_bin(pcd_path: &Path) -> Result<Vec<InfoPoint>> {
-    let mut input = BufReader::new(
-        File::open(pcd_path)
-            .with_context(|| format!("Failed to open file {}", pcd_path.display()))?,
+/// Checks that a scan of `len` bytes holds a whole number of points.
+pub fn check_scan_len(pcd_path: &Path, len: usize) -> Result<()> {
+    let point_size = mem::size_of::<[f32; 4]>();
+    ensure!(
+        len.is_multiple_of(point_size),
+        "Truncated scan {}. Expect a multiple of {point_size} bytes, but found {len} bytes.",
+        pcd_path.display()
     );
+    Ok(())
+}
 
-    macro_rules! read_f32 {
-        () => {{
-            input.read_f32::<LittleEndian>()
-        }};
+/// Loads the point cloud of a frame in the given format. `.bin` scans
+/// are decompressed first if the file name ends with a [Codec]
+/// extension such as `.bin.zst`, and have no channels besides the
+/// intensity.
+pub fn load_cloud(
+    storage: &dyn Storage,
+    pcd_path: &Path,
+    format: CloudFormat,
+) -> Result<PointCloud> {
+    match format {
+        CloudFormat::Bin => Ok(PointCloud::from_scan(VelodyneScan::open(
+            storage, pcd_path,
+        )?)),
+        CloudFormat::Pcd => load_pcd(storage, pcd_path),
+        CloudFormat::Ply => load_ply(storage, pcd_path),
+        CloudFormat::Las | CloudFormat::Laz => load_las(storage, pcd_path),
     }
+}
+
+/// Loads an ASCII, binary or binary_compressed PCD file with any field
+/// layout. `x`, `y`, `z` and `intensity` (or `i`) fill the positions
+/// and every other field becomes a [Channel] of the same type. A packed
+/// `rgb`/`rgba` field is split into the `u8` channels `r`, `g` and `b`,
+/// and fields with `COUNT > 1` are split into `<name>_<idx>`.
+pub fn load_pcd(storage: &dyn Storage, pcd_path: &Path) -> Result<PointCloud> {
+    let mut bytes = read_decoded(storage, pcd_path)?;
+    if let Some(binary) = decompress_pcd(&bytes)
+        .with_context(|| format!("Corrupted binary_compressed data in {}", pcd_path.display()))?
+    {
+        bytes = binary;
+    }
+
+    let reader = DynReader::from_reader(Cursor::new(bytes))
+        .with_context(|| format!("Failed to parse PCD header of {}", pcd_path.display()))?;
+    let field_names: Vec<String> = reader
+        .meta()
+        .field_defs
+        .fields
+        .iter()
+        .map(|def| def.name.clone())
+        .collect();
+
+    let position = |name: &str| field_names.iter().position(|field| field == name);
+    let (Some(x_idx), Some(y_idx), Some(z_idx)) = (position("x"), position("y"), position("z"))
+    else {
+        bail!("{} has no x, y and z fields", pcd_path.display());
+    };
+    let intensity_idx = position("intensity").or_else(|| position("i"));
+
+    let mut points = vec![];
+    let mut channels: BTreeMap<String, Channel> = BTreeMap::new();
+
+    for record in reader {
+        let DynRecord(fields) = record?;
+        let first = |idx: usize| field_values(&fields[idx])[0] as f32;
+
+        points.push([
+            first(x_idx),
+            first(y_idx),
+            first(z_idx),
+            intensity_idx.map_or(0.0, first),
+        ]);
+
+        for (idx, (name, field)) in field_names.iter().zip(&fields).enumerate() {
+            if [Some(x_idx), Some(y_idx), Some(z_idx), intensity_idx].contains(&Some(idx)) {
+                continue;
+            }
 
-    macro_rules! try_read_f32 {
-        () => {{
-            let mut buf = [0u8; 4];
-            let cnt = input.read(&mut buf)?;
+            if name == "rgb" || name == "rgba" {
+                let packed = match field {
+                    Field::F32(values) => values[0].to_bits(),
+                    Field::U32(values) => values[0],
+                    Field::I32(values) => values[0] as u32,
+                    _ => bail!(
+                        "unsupported type for field {name} in {}",
+                        pcd_path.display()
+                    ),
+                };
+                for (channel, shift) in [("r", 16), ("g", 8), ("b", 0)] {
+                    let value = ((packed >> shift) & 0xff) as f64;
+                    channels
+                        .entry(channel.to_string())
+                        .or_insert_with(|| Channel::new(ChannelKind::U8))
+                        .push(value);
+                }
+                continue;
+            }
 
-            match cnt {
-                4 => Ok(Some(f32::from_le_bytes(buf))),
-                0 => Ok(None),
-                cnt => Err(io::Error::new(
-                    io::ErrorKind::UnexpectedEof,
-                    format!("Truncated f32 found. Expect 4 bytes, but read {cnt} bytes."),
-                )),
+            let kind = field_kind(field);
+            let values = field_values(field);
+            if let [value] = values[..] {
+                channels
+                    .entry(name.clone())
+                    .or_insert_with(|| Channel::new(kind))
+                    .push(value);
+            } else {
+                for (nth, value) in values.into_iter().enumerate() {
+                    channels
+                        .entry(format!("{name}_{nth}"))
+                        .or_insert_with(|| Channel::new(kind))
+                        .push(value);
+                }
             }
-        }};
+        }
     }
 
-    let mut points = vec![];
+    let mut cloud = PointCloud::from_points(points);
+    for (name, channel) in channels {
+        cloud
+            .insert_channel(name, channel)
+            .with_context(|| format!("Inconsistent fields in {}", pcd_path.display()))?;
+    }
+    Ok(cloud)
+}
+
+fn field_kind(field: &Field) -> ChannelKind {
+    match field {
+        Field::I8(_) => ChannelKind::I8,
+        Field::I16(_) => ChannelKind::I16,
+        Field::I32(_) => ChannelKind::I32,
+        Field::U8(_) => ChannelKind::U8,
+        Field::U16(_) => ChannelKind::U16,
+        Field::U32(_) => ChannelKind::U32,
+        Field::F32(_) => ChannelKind::F32,
+        Field::F64(_) => ChannelKind::F64,
+    }
+}
+
+fn field_values(field: &Field) -> Vec<f64> {
+    match field {
+        Field::I8(values) => values.iter().map(|&val| val as f64).collect(),
+        Field::I16(values) => values.iter().map(|&val| val as f64).collect(),
+        Field::I32(values) => values.iter().map(|&val| val as f64).collect(),
+        Field::U8(values) => values.iter().map(|&val| val as f64).collect(),
+        Field::U16(values) => values.iter().map(|&val| val as f64).collect(),
+        Field::U32(values) => values.iter().map(|&val| val as f64).collect(),
+        Field::F32(values) => values.iter().map(|&val| val as f64).collect(),
+        Field::F64(values) => values.clone(),
+    }
+}
+
+/// Reads a whole file, decompressing it if the file name ends with a
+/// [Codec] extension.
+pub fn read_decoded(storage: &dyn Storage, path: &Path) -> Result<Vec<u8>> {
+    let file = storage
+        .open(path)
+        .with_context(|| format!("Failed to open file {}", path.display()))?;
+    let mut input = match Codec::from_path(path) {
+        Some(codec) => codec.decoder(file)?,
+        None => file,
+    };
+    let mut bytes = vec![];
+    input.read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Rewrites a `binary_compressed` PCD into the equivalent `binary` one,
+/// which pcd-rs can parse. Returns `None` for other encodings.
+///
+/// The compressed body is LZF data laid out field by field, while the
+/// binary body stores whole points one after another.
+fn decompress_pcd(bytes: &[u8]) -> Result<Option<Vec<u8>>> {
+    let mut line_start = 0;
+    let mut sizes: Vec<usize> = vec![];
+    let mut counts: Vec<usize> = vec![];
+    let mut num_points: Option<usize> = None;
 
-    loop {
-        let Some(x) = try_read_f32!()? else {
-            break;
+    let body_start = loop {
+        let Some(len) = bytes[line_start..].iter().position(|&byte| byte == b'\n') else {
+            bail!("PCD header has no DATA line");
         };
-        let y = read_f32!()?;
-        let z = read_f32!()?;
-        let intensity = read_f32!()?;
-
-        let point = InfoPoint {
-            point: [x, y, z].into(),
-            intensity,
-            device_id: None,
-            active: None,
+        let line = std::str::from_utf8(&bytes[line_start..line_start + len])?;
+        let mut tokens = line.split_whitespace();
+        let parse_all = |tokens: std::str::SplitWhitespace| -> Result<Vec<usize>> {
+            Ok(tokens.map(str::parse).collect::<Result<_, _>>()?)
         };
-        points.push(point);
+
+        match tokens.next() {
+            Some("SIZE") => sizes = parse_all(tokens)?,
+            Some("COUNT") => counts = parse_all(tokens)?,
+            Some("POINTS") => num_points = parse_all(tokens)?.first().copied(),
+            Some("DATA") => {
+                if tokens.next() != Some("binary_compressed") {
+                    return Ok(None);
+                }
+                break line_start + len + 1;
+            }
+            _ => {}
+        }
+        line_start += len + 1;
+    };
+
+    let Some(num_points) = num_points else {
+        bail!("PCD header has no POINTS line");
+    };
+    if counts.is_empty() {
+        counts = vec![1; sizes.len()];
+    }
+    ensure!(sizes.len() == counts.len(), "SIZE and COUNT lengths differ");
+
+    let body = &bytes[body_start..];
+    ensure!(body.len() >= 8, "missing compressed size header");
+    let compressed_size = u32::from_le_bytes(body[0..4].try_into().unwrap()) as usize;
+    let uncompressed_size = u32::from_le_bytes(body[4..8].try_into().unwrap()) as usize;
+    ensure!(
+        body.len() >= 8 + compressed_size,
+        "expect {compressed_size} compressed bytes, but found {}",
+        body.len() - 8
+    );
+    let data = lzf_decompress(&body[8..8 + compressed_size], uncompressed_size)?;
+
+    let field_sizes: Vec<usize> = sizes.iter().zip(&counts).map(|(s, c)| s * c).collect();
+    let point_size: usize = field_sizes.iter().sum();
+    ensure!(
+        data.len() == point_size * num_points,
+        "expect {} bytes for {num_points} points, but decompressed {} bytes",
+        point_size * num_points,
+        data.len()
+    );
+
+    let field_offsets: Vec<usize> = field_sizes
+        .iter()
+        .scan(0, |offset, &size| {
+            let start = *offset;
+            *offset += size * num_points;
+            Some(start)
+        })
+        .collect();
+
+    let mut binary = bytes[..line_start].to_vec();
+    binary.extend_from_slice(b"DATA binary\n");
+    binary.reserve(data.len());
+    for idx in 0..num_points {
+        for (&offset, &size) in field_offsets.iter().zip(&field_sizes) {
+            let start = offset + idx * size;
+            binary.extend_from_slice(&data[start..start + size]);
+        }
+    }
+
+    Ok(Some(binary))
+}
+
+/// Decompresses LZF data as produced by liblzf and PCL.
+fn lzf_decompress(input: &[u8], output_size: usize) -> Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(output_size);
+    let mut pos = 0;
+
+    while pos < input.len() {
+        let ctrl = input[pos] as usize;
+        pos += 1;
+
+        if ctrl < 32 {
+            // Literal run of ctrl + 1 bytes
+            let len = ctrl + 1;
+            ensure!(pos + len <= input.len(), "truncated literal run");
+            output.extend_from_slice(&input[pos..pos + len]);
+            pos += len;
+        } else {
+            // Back reference
+            let mut len = ctrl >> 5;
+            if len == 7 {
+                ensure!(pos < input.len(), "truncated back reference");
+                len += input[pos] as usize;
+                pos += 1;
+            }
+            ensure!(pos < input.len(), "truncated back reference");
+            let distance = ((ctrl & 0x1f) << 8) + input[pos] as usize + 1;
+            pos += 1;
+            len += 2;
+
+            ensure!(distance <= output.len(), "back reference out of range");
+            let start = output.len() - distance;
+            for idx in start..start + len {
+                output.push(output[idx]);
+            }
+        }
+    }
+
+    ensure!(
+        output.len() == output_size,
+        "expect {output_size} bytes, but decompressed {} bytes",
+        output.len()
+    );
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kitti_format::MemStorage;
+
+    const HEADER: &str = "\
+# .PCD v0.7 - Point Cloud Data file format
+VERSION 0.7
+FIELDS x y z intensity ring
+SIZE 4 4 4 4 2
+TYPE F F F F U
+COUNT 1 1 1 1 1
+WIDTH 2
+HEIGHT 1
+VIEWPOINT 0 0 0 1 0 0 0
+POINTS 2
+";
+    const POINTS: [([f32; 4], u16); 2] = [([1.0, 2.0, 3.0, 0.5], 7), ([-4.0, 5.5, -6.0, 0.25], 31)];
+
+    fn load(name: &str, data: Vec<u8>) -> PointCloud {
+        let mut storage = MemStorage::new();
+        storage.insert(name, data);
+        load_pcd(&storage, Path::new(name)).unwrap()
+    }
+
+    fn check(cloud: &PointCloud) {
+        let points: Vec<_> = POINTS.iter().map(|&(point, _)| point).collect();
+        let rings: Vec<_> = POINTS.iter().map(|&(_, ring)| ring).collect();
+        assert_eq!(cloud.points(), points);
+        assert_eq!(cloud.channel("ring"), Some(&Channel::U16(rings)));
+    }
+
+    fn binary_pcd() -> Vec<u8> {
+        let mut data = format!("{HEADER}DATA binary\n").into_bytes();
+        for ([x, y, z, intensity], ring) in POINTS {
+            for value in [x, y, z, intensity] {
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&ring.to_le_bytes());
+        }
+        data
     }
 
-    Ok(points)
+    /// Stores the fields one after another as LZF literal runs.
+    fn binary_compressed_pcd() -> Vec<u8> {
+        let mut fields = vec![];
+        for idx in 0..4 {
+            for (point, _) in POINTS {
+                fields.extend_from_slice(&point[idx].to_le_bytes());
+            }
+        }
+        for (_, ring) in POINTS {
+            fields.extend_from_slice(&ring.to_le_bytes());
+        }
+        let mut compressed = vec![];
+        for run in fields.chunks(32) {
+            compressed.push(run.len() as u8 - 1);
+            compressed.extend_from_slice(run);
+        }
+
+        let mut data = format!("{HEADER}DATA binary_compressed\n").into_bytes();
+        data.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(fields.len() as u32).to_le_bytes());
+        data.extend_from_slice(&compressed);
+        data
+    }
+
+    #[test]
+    fn decodes_ascii_pcd() {
+        let data = format!("{HEADER}DATA ascii\n1 2 3 0.5 7\n-4 5.5 -6 0.25 31\n");
+        check(&load("velodyne/000000.pcd", data.into_bytes()));
+    }
+
+    #[test]
+    fn decodes_binary_pcd() {
+        check(&load("velodyne/000000.pcd", binary_pcd()));
+    }
+
+    #[test]
+    fn decodes_binary_compressed_pcd() {
+        let data = binary_compressed_pcd();
+        assert_eq!(decompress_pcd(&data).unwrap(), Some(binary_pcd()));
+        check(&load("velodyne/000000.pcd", data));
+    }
+
+    #[test]
+    fn lzf_back_reference() {
+        // The literal run "abc" followed by 6 bytes copied from 3 back
+        let input = [2, b'a', b'b', b'c', 4 << 5, 2];
+        assert_eq!(lzf_decompress(&input, 9).unwrap(), b"abcabcabc");
+        assert!(lzf_decompress(&input, 8).is_err());
+        assert!(lzf_decompress(&[4 << 5, 2], 6).is_err());
+    }
 }
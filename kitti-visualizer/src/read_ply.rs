@@ -0,0 +1,333 @@
+use crate::{
+    point_cloud::{Channel, ChannelKind, PointCloud},
+    read_pcd::read_decoded,
+};
+use anyhow::{bail, ensure, Context, Result};
+use kitti_format::Storage;
+use std::{collections::BTreeMap, path::Path, str::SplitAsciiWhitespace};
+
+/// Loads the vertices of an ASCII or binary PLY file. `x`, `y`, `z` and
+/// `intensity` fill the positions. `red`, `green` and `blue` are stored
+/// as the `r`, `g` and `b` channels and every other scalar vertex
+/// property, e.g. `classification`, becomes a [Channel] of the same
+/// type. Faces and other elements are skipped.
+pub fn load_ply(storage: &dyn Storage, ply_path: &Path) -> Result<PointCloud> {
+    let bytes = read_decoded(storage, ply_path)?;
+    let (header, body) = parse_header(&bytes)
+        .with_context(|| format!("Invalid PLY header in {}", ply_path.display()))?;
+
+    let mut body = match header.format {
+        Format::Ascii => Body::Ascii(std::str::from_utf8(body)?.split_ascii_whitespace()),
+        Format::BinaryLittleEndian => Body::Binary {
+            data: body,
+            big_endian: false,
+        },
+        Format::BinaryBigEndian => Body::Binary {
+            data: body,
+            big_endian: true,
+        },
+    };
+
+    let mut points = vec![];
+    let mut channels: BTreeMap<String, Channel> = BTreeMap::new();
+
+    for element in &header.elements {
+        let is_vertex = element.name == "vertex";
+        let position = |name: &str| element.properties.iter().position(|prop| prop.name == name);
+        let (x_idx, y_idx, z_idx) = (position("x"), position("y"), position("z"));
+        let intensity_idx = position("intensity");
+        if is_vertex && (x_idx.is_none() || y_idx.is_none() || z_idx.is_none()) {
+            bail!("{} has no x, y and z vertex properties", ply_path.display());
+        }
+
+        let mut values = vec![0.0; element.properties.len()];
+
+        for _ in 0..element.count {
+            for (value, prop) in values.iter_mut().zip(&element.properties) {
+                match prop.list_len {
+                    Some(len_kind) => {
+                        let len = body.next(len_kind)? as usize;
+                        for _ in 0..len {
+                            body.next(prop.kind)?;
+                        }
+                    }
+                    None => *value = body.next(prop.kind)?,
+                }
+            }
+
+            if !is_vertex {
+                continue;
+            }
+
+            let value_of = |idx: Option<usize>| idx.map_or(0.0, |idx| values[idx] as f32);
+            points.push([
+                value_of(x_idx),
+                value_of(y_idx),
+                value_of(z_idx),
+                value_of(intensity_idx),
+            ]);
+
+            for (idx, prop) in element.properties.iter().enumerate() {
+                if prop.list_len.is_some()
+                    || [x_idx, y_idx, z_idx, intensity_idx].contains(&Some(idx))
+                {
+                    continue;
+                }
+                let name = match prop.name.as_str() {
+                    "red" | "diffuse_red" => "r",
+                    "green" | "diffuse_green" => "g",
+                    "blue" | "diffuse_blue" => "b",
+                    name => name,
+                };
+                channels
+                    .entry(name.to_string())
+                    .or_insert_with(|| Channel::new(prop.kind))
+                    .push(values[idx]);
+            }
+        }
+
+        if is_vertex {
+            break;
+        }
+    }
+
+    let mut cloud = PointCloud::from_points(points);
+    for (name, channel) in channels {
+        cloud.insert_channel(name, channel)?;
+    }
+    Ok(cloud)
+}
+
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Property {
+    name: String,
+    kind: ChannelKind,
+    /// The type of the length prefix for list properties.
+    list_len: Option<ChannelKind>,
+}
+
+fn kind_from_name(name: &str) -> Result<ChannelKind> {
+    Ok(match name {
+        "char" | "int8" => ChannelKind::I8,
+        "uchar" | "uint8" => ChannelKind::U8,
+        "short" | "int16" => ChannelKind::I16,
+        "ushort" | "uint16" => ChannelKind::U16,
+        "int" | "int32" => ChannelKind::I32,
+        "uint" | "uint32" => ChannelKind::U32,
+        "float" | "float32" => ChannelKind::F32,
+        "double" | "float64" => ChannelKind::F64,
+        _ => bail!("unknown property type {name}"),
+    })
+}
+
+/// Splits a PLY file into its parsed header and the body after
+/// `end_header`.
+fn parse_header(bytes: &[u8]) -> Result<(Header, &[u8])> {
+    let mut pos = 0;
+    let mut next_line = || -> Result<&str> {
+        let Some(len) = bytes[pos..].iter().position(|&byte| byte == b'\n') else {
+            bail!("missing end_header");
+        };
+        let line = std::str::from_utf8(&bytes[pos..pos + len])?;
+        pos += len + 1;
+        Ok(line.trim_end_matches('\r'))
+    };
+
+    ensure!(next_line()? == "ply", "missing the ply magic");
+
+    let mut format = None;
+    let mut elements: Vec<Element> = vec![];
+
+    loop {
+        let line = next_line()?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens[..] {
+            ["end_header"] => break,
+            ["format", name, _version] => {
+                format = Some(match name {
+                    "ascii" => Format::Ascii,
+                    "binary_little_endian" => Format::BinaryLittleEndian,
+                    "binary_big_endian" => Format::BinaryBigEndian,
+                    _ => bail!("unknown format {name}"),
+                });
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse()?,
+                properties: vec![],
+            }),
+            ["property", "list", len_kind, kind, name] => {
+                let Some(element) = elements.last_mut() else {
+                    bail!("property {name} appears before any element");
+                };
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: kind_from_name(kind)?,
+                    list_len: Some(kind_from_name(len_kind)?),
+                });
+            }
+            ["property", kind, name] => {
+                let Some(element) = elements.last_mut() else {
+                    bail!("property {name} appears before any element");
+                };
+                element.properties.push(Property {
+                    name: name.to_string(),
+                    kind: kind_from_name(kind)?,
+                    list_len: None,
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let Some(format) = format else {
+        bail!("missing the format line");
+    };
+    Ok((Header { format, elements }, &bytes[pos..]))
+}
+
+enum Body<'a> {
+    Ascii(SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Body<'_> {
+    fn next(&mut self, kind: ChannelKind) -> Result<f64> {
+        match self {
+            Body::Ascii(tokens) => {
+                let Some(token) = tokens.next() else {
+                    bail!("unexpected end of data");
+                };
+                Ok(token.parse()?)
+            }
+            Body::Binary { data, big_endian } => {
+                let size = kind.size();
+                ensure!(data.len() >= size, "unexpected end of data");
+                let (bytes, rest) = (*data).split_at(size);
+                *data = rest;
+
+                let mut buf = [0u8; 8];
+                buf[..size].copy_from_slice(bytes);
+                if *big_endian {
+                    buf[..size].reverse();
+                }
+
+                Ok(match kind {
+                    ChannelKind::I8 => buf[0] as i8 as f64,
+                    ChannelKind::U8 => buf[0] as f64,
+                    ChannelKind::I16 => i16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    ChannelKind::U16 => u16::from_le_bytes([buf[0], buf[1]]) as f64,
+                    ChannelKind::I32 => i32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    ChannelKind::U32 => u32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    ChannelKind::F32 => f32::from_le_bytes(buf[..4].try_into().unwrap()) as f64,
+                    ChannelKind::F64 => f64::from_le_bytes(buf),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kitti_format::MemStorage;
+
+    fn load(data: Vec<u8>) -> PointCloud {
+        let mut storage = MemStorage::new();
+        storage.insert("velodyne/000000.ply", data);
+        load_ply(&storage, Path::new("velodyne/000000.ply")).unwrap()
+    }
+
+    #[test]
+    fn parses_ascii_ply() {
+        let data = "\
+ply
+format ascii 1.0
+comment written by hand
+element vertex 2
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+property ushort classification
+element face 1
+property list uchar int vertex_indices
+end_header
+1 2 3 255 0 10 2
+-1.5 0 4 0 128 20 6
+3 0 1 0
+";
+        let cloud = load(data.as_bytes().to_vec());
+
+        assert_eq!(
+            cloud.points(),
+            [[1.0, 2.0, 3.0, 0.0], [-1.5, 0.0, 4.0, 0.0]]
+        );
+        assert_eq!(cloud.channel("r"), Some(&Channel::U8(vec![255, 0])));
+        assert_eq!(cloud.channel("g"), Some(&Channel::U8(vec![0, 128])));
+        assert_eq!(cloud.channel("b"), Some(&Channel::U8(vec![10, 20])));
+        assert_eq!(
+            cloud.channel("classification"),
+            Some(&Channel::U16(vec![2, 6]))
+        );
+    }
+
+    #[test]
+    fn parses_binary_ply() {
+        let header = "\
+ply
+format binary_big_endian 1.0
+element face 1
+property list uchar int vertex_indices
+element vertex 1
+property double x
+property double y
+property double z
+property float intensity
+property int ring
+end_header
+";
+        let mut data = header.as_bytes().to_vec();
+        data.push(3);
+        for idx in [0i32, 1, 2] {
+            data.extend_from_slice(&idx.to_be_bytes());
+        }
+        for value in [0.5f64, -2.0, 8.25] {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.extend_from_slice(&0.75f32.to_be_bytes());
+        data.extend_from_slice(&(-3i32).to_be_bytes());
+
+        let cloud = load(data);
+
+        assert_eq!(cloud.points(), [[0.5, -2.0, 8.25, 0.75]]);
+        assert_eq!(cloud.channel("ring"), Some(&Channel::I32(vec![-3])));
+    }
+
+    #[test]
+    fn rejects_truncated_ply() {
+        let data = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\nend_header\n1 2 3\n";
+        let mut storage = MemStorage::new();
+        storage.insert("velodyne/000000.ply", data);
+        assert!(load_ply(&storage, Path::new("velodyne/000000.ply")).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{
+    compress::write_atomic,
+    utils::{
+        export_each_frame, list_image_indices, load_camera_frame, load_frame_cloud, CloudConfig,
+        ImageDatasetOpts,
+    },
+};
+use anyhow::{Context, Result};
+use clap::Args;
+use kitti_format::{ImageProjection, KittiCamera, KittiSource, PixelMap};
+use nalgebra as na;
+use std::{fs, path::PathBuf};
+
+#[derive(Debug, Clone, Args)]
+pub struct SparseDepthOpts {
+    #[clap(flatten)]
+    pub dataset: ImageDatasetOpts,
+    /// Directory to write the `<index>.png` depth maps to.
+    pub output_dir: PathBuf,
+}
+
+/// Projects the scan of every frame with an `image_2` picture into a
+/// sparse depth map in the format of the KITTI depth benchmark. Frames
+/// that fail are skipped and reported at the end.
+pub fn export_sparse_depth(opts: &SparseDepthOpts) -> Result<()> {
+    let SparseDepthOpts {
+        dataset:
+            ImageDatasetOpts {
+                ref input,
+                ref split,
+                cloud_format,
+            },
+        ref output_dir,
+    } = *opts;
+
+    let source = KittiSource::new(input, split)?;
+    let indices = list_image_indices(&source)?;
+
+    fs::create_dir_all(output_dir)?;
+    let cloud_config = CloudConfig::scan_only(cloud_format);
+
+    export_each_frame(&indices, |index| {
+        let camera = load_camera_frame(index, &source, &cloud_config)?
+            .with_context(|| format!("no calibration for frame {index}"))?;
+        let cloud = load_frame_cloud(index, &source, &cloud_config)?;
+        let projection = ImageProjection::new(&camera.calib, KittiCamera::Cam2);
+
+        let points: Vec<na::Point3<f64>> = cloud
+            .points()
+            .iter()
+            .map(|&[x, y, z, _]| na::Point3::new(x, y, z).cast())
+            .collect();
+        let depth_map = PixelMap::from_points(
+            &points,
+            &projection,
+            camera.image.width(),
+            camera.image.height(),
+        );
+
+        let mut data = vec![];
+        depth_map.write_png(&mut data)?;
+        write_atomic(&output_dir.join(format!("{index:0>6}.png")), &data)
+    })?;
+
+    eprintln!(
+        "wrote {} depth maps into {}",
+        indices.len(),
+        output_dir.display()
+    );
+    Ok(())
+}
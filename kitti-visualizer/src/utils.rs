@@ -1,26 +1,198 @@
 use crate::{
-    read_pcd::{load_bin, InfoPoint},
+    compress::Codec,
+    image_view::load_image,
+    point_cloud::{Channel, PointCloud, ACTIVE, INSTANCE, SEMANTIC},
+    read_pcd::{load_cloud, read_decoded, CloudFormat},
     PcdFormat,
 };
-use anyhow::{Context, Result};
-use kitti_format::{KittiCalib, KittiObject};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use image::RgbImage;
+use kitti_format::{
+    BoxIndex, BoxMembership, GroundPlane, KittiCalib, KittiCamera, KittiObject, KittiSource,
+    LidarExtrinsic, PixelMap, Storage,
+};
 use nalgebra as na;
-use std::{fs, io, path::Path};
+use rayon::prelude::*;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    iter,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Once,
+};
+
+/// The directory of the primary LiDAR. Boxes are given in its frame.
+pub const PRIMARY_LIDAR: &str = "velodyne";
+
+/// The edges of a box as pairs of indices into its vertices.
+pub const BOX_EDGES: &[(usize, usize)] = &[
+    (0, 1),
+    (0, 2),
+    (1, 3),
+    (2, 3),
+    (4, 5),
+    (4, 6),
+    (5, 7),
+    (6, 7),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+    (1, 7),
+    (3, 5),
+];
+
+/// Where the point cloud of a frame comes from.
+#[derive(Debug, Clone)]
+pub struct CloudConfig {
+    pub format: CloudFormat,
+    /// Directories of LiDARs besides [PRIMARY_LIDAR].
+    pub lidars: Vec<String>,
+    /// A directory of per-frame masks marking the active points.
+    pub mask_dir: Option<PathBuf>,
+    /// A directory of `<index>.png` disparity maps of the left colour
+    /// camera, shown as a pseudo-LiDAR cloud.
+    pub disparity_dir: Option<PathBuf>,
+    /// A directory of `<index>.png` depth maps of the left colour camera,
+    /// e.g. predicted dense depth, shown as points.
+    pub depth_dir: Option<PathBuf>,
+}
+
+impl CloudConfig {
+    /// Loads the [PRIMARY_LIDAR] scan alone, without masks or maps.
+    pub fn scan_only(format: CloudFormat) -> Self {
+        Self {
+            format,
+            lidars: vec![],
+            mask_dir: None,
+            disparity_dir: None,
+            depth_dir: None,
+        }
+    }
+}
+
+/// The dataset of the commands that go through the frames with an
+/// `image_2` picture.
+#[derive(Debug, Clone, Args)]
+pub struct ImageDatasetOpts {
+    /// Dataset directory or KITTI zip archive containing the velodyne,
+    /// image_2 and calib directories. Repeat to combine several archives.
+    #[clap(short, long, required = true)]
+    pub input: Vec<PathBuf>,
+    /// The split directory inside zip archives.
+    #[clap(long, default_value = "training")]
+    pub split: String,
+    /// The file format of the point clouds in the velodyne directory.
+    #[clap(long, default_value = "bin")]
+    pub cloud_format: CloudFormat,
+}
+
+/// A margin in metres along the length, width and height of a box,
+/// given as `<m>` for all three or `<x>,<y>,<z>`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Margin(pub na::Vector3<f64>);
+
+impl FromStr for Margin {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let values: Vec<f64> = text
+            .split(',')
+            .map(|value| value.trim().parse())
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("invalid margin {text:?}"))?;
+        match *values.as_slice() {
+            [margin] => Ok(Self(na::Vector3::repeat(margin))),
+            [x, y, z] => Ok(Self(na::Vector3::new(x, y, z))),
+            _ => bail!("expect one or three values in margin {text:?}"),
+        }
+    }
+}
+
+/// The margin of one class, given as `<class>=<margin>`, e.g.
+/// `Pedestrian=0.3,0.3,0.1`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassMargin {
+    pub class: String,
+    pub margin: Margin,
+}
+
+impl FromStr for ClassMargin {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let (class, margin) = text
+            .split_once('=')
+            .ok_or_else(|| anyhow!("expect <class>=<margin> but get {text:?}"))?;
+        Ok(Self {
+            class: class.trim().to_string(),
+            margin: margin.parse()?,
+        })
+    }
+}
+
+/// How far outside a box points still count as inside it, for the
+/// point counts and colours.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoxMargins {
+    pub default: na::Vector3<f64>,
+    /// Classes needing a different tolerance, e.g. `Pedestrian`.
+    pub classes: HashMap<String, na::Vector3<f64>>,
+}
+
+impl BoxMargins {
+    pub fn new(default: Margin, classes: &[ClassMargin]) -> Self {
+        Self {
+            default: default.0,
+            classes: classes
+                .iter()
+                .map(|ClassMargin { class, margin }| (class.clone(), margin.0))
+                .collect(),
+        }
+    }
+
+    pub fn of(&self, class: &str) -> na::Vector3<f64> {
+        self.classes.get(class).copied().unwrap_or(self.default)
+    }
+
+    /// Adds `delta` to every margin along every axis.
+    pub fn grow(&mut self, delta: f64) {
+        let delta = na::Vector3::repeat(delta);
+        self.default += delta;
+        for margin in self.classes.values_mut() {
+            *margin += delta;
+        }
+    }
+}
 
 pub struct FrameData {
     pub objects: Vec<KittiObject>,
-    pub points_in_range: Vec<InfoPoint>,
-    pub points_out_range: Vec<InfoPoint>,
-    pub num_points_map: Vec<usize>,
-}
-
-pub fn get_indices_from_ann_dir(ann_dir: &Path) -> Vec<usize> {
-    let mut entries = fs::read_dir(ann_dir)
-        .unwrap()
-        .map(|res| res.map(|e| e.path()))
-        .collect::<io::Result<Vec<_>>>()
-        .unwrap();
-    entries.sort();
+    pub cloud: PointCloud,
+    /// The margins [FrameData::membership] was found with.
+    pub box_margins: BoxMargins,
+    /// The boxes the points of the cloud lie in.
+    pub membership: BoxMembership,
+    /// The road plane in the velodyne frame, if the frame has one.
+    pub ground_plane: Option<GroundPlane>,
+    /// The `image_2` picture of the frame with its calibration.
+    pub camera: Option<CameraFrame>,
+}
+
+pub struct CameraFrame {
+    pub image: RgbImage,
+    pub calib: KittiCalib,
+    /// The `image_3` picture of the right colour camera.
+    pub right_image: Option<RgbImage>,
+    pub disparity: Option<PixelMap>,
+    pub depth: Option<PixelMap>,
+}
+
+pub fn get_indices_from_ann_dir(storage: &dyn Storage, ann_dir: &Path) -> Result<Vec<usize>> {
+    let entries = storage
+        .list(ann_dir)
+        .with_context(|| format!("unable to list {} in {storage}", ann_dir.display()))?;
     let indices: Vec<usize> = entries
         .iter()
         .filter_map(|path| {
@@ -35,114 +207,333 @@ pub fn get_indices_from_ann_dir(ann_dir: &Path) -> Vec<usize> {
             }
         })
         .collect();
-    indices
-}
-
-pub fn in_bbox(point: &na::Point3<f64>, objects: &[KittiObject]) -> bool {
-    let mut result = false;
-    for obj in objects {
-        let bbox = &obj.bbox3d;
-        let enlarge_offset = 0.1;
-        let origin_point = bbox.pose.inverse() * point;
-        if origin_point.x < bbox.extents.x / 2. + enlarge_offset
-            && origin_point.x > -bbox.extents.x / 2. - enlarge_offset
-            && origin_point.y < bbox.extents.y / 2. + enlarge_offset
-            && origin_point.y > -bbox.extents.y / 2. - enlarge_offset
-            && origin_point.z < bbox.extents.z / 2. + enlarge_offset
-            && origin_point.z > -bbox.extents.z / 2. - enlarge_offset
-        {
-            result = true;
-            break;
-        }
+    Ok(indices)
+}
+
+/// Lists the frames with an `image_2` picture in ascending order.
+pub fn list_image_indices(source: &KittiSource) -> Result<Vec<i32>> {
+    let image_dir = Path::new(KittiCamera::Cam2.image_dir());
+    let mut indices: Vec<i32> = source
+        .list(image_dir)
+        .with_context(|| format!("unable to list {} in {source}", image_dir.display()))?
+        .iter()
+        .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+        .filter_map(|path| path.file_stem()?.to_str()?.parse().ok())
+        .collect();
+    indices.sort_unstable();
+    Ok(indices)
+}
+
+/// Runs `export` on every frame in parallel. A frame that fails does not
+/// stop the others; the errors are printed once all frames are done and
+/// the run then fails.
+pub fn export_each_frame<F>(indices: &[i32], export: F) -> Result<()>
+where
+    F: Fn(i32) -> Result<()> + Sync,
+{
+    let errors: Vec<(i32, anyhow::Error)> = indices
+        .par_iter()
+        .filter_map(|&index| Some((index, export(index).err()?)))
+        .collect();
+
+    for (index, err) in &errors {
+        eprintln!("Error: frame {index:0>6}: {err:#}");
+    }
+    if !errors.is_empty() {
+        bail!("{} of {} frames failed", errors.len(), indices.len());
     }
+    Ok(())
+}
+
+/// Finds the point cloud of a frame, trying the uncompressed file first
+/// and then the compressed variants.
+pub fn find_scan_path(
+    storage: &dyn Storage,
+    pcd_dir: &Path,
+    index: i32,
+    cloud_format: CloudFormat,
+) -> Result<PathBuf> {
+    let file_name = format!("{:0>6}.{}", index, cloud_format.extension());
+    let compressed_names = Codec::ALL
+        .iter()
+        .map(|codec| format!("{file_name}.{}", codec.extension()));
 
-    result
+    iter::once(file_name.clone())
+        .chain(compressed_names)
+        .map(|name| pcd_dir.join(name))
+        .find(|path| storage.exists(path))
+        .with_context(|| format!("no scan for frame {index} in {}", pcd_dir.display()))
+}
+
+pub fn load_calib(index: i32, storage: &dyn Storage) -> Result<KittiCalib> {
+    let calib_path = Path::new("calib").join(format!("{index:0>6}.txt"));
+    KittiCalib::from_storage(storage, &calib_path)
+        .with_context(|| format!("unable to read {}", calib_path.display()))
 }
 
 pub fn get_objects_from_frame_id(
     index: i32,
-    kitti_dir: &Path,
-    supervisely_ann_dir: Option<&Path>,
+    storage: &dyn Storage,
+    supervisely_storage: Option<&dyn Storage>,
     pcd_format: PcdFormat,
-) -> Vec<KittiObject> {
-    let objects = if supervisely_ann_dir.is_none() {
-        let exclude_classes = vec!["DontCare".into()];
-        let ann_dir = kitti_dir.join("label_2");
-        let calib_dir = kitti_dir.join("calib");
-        let ann_path = ann_dir.join(format!("{:0>6}.txt", index.to_string()));
-        let calib_path = calib_dir.join(format!("{:0>6}.txt", index.to_string()));
-
-        let calib = KittiCalib::from_file(calib_path);
-
-        if pcd_format == PcdFormat::Philly {
-            kitti_format::read_ann_file_philly(ann_path, &calib, &exclude_classes)
+) -> Result<Vec<KittiObject>> {
+    let objects = match supervisely_storage {
+        None => {
+            let exclude_classes = vec!["DontCare".into()];
+            let ann_path = Path::new("label_2").join(format!("{:0>6}.txt", index.to_string()));
+            let calib = load_calib(index, storage)?;
+
+            if pcd_format == PcdFormat::Philly {
+                kitti_format::read_ann_philly_from_storage(
+                    storage,
+                    &ann_path,
+                    &calib,
+                    &exclude_classes,
+                )
+            } else {
+                kitti_format::read_ann_from_storage(storage, &ann_path, &calib, &exclude_classes)
+            }
+            .with_context(|| format!("unable to read {}", ann_path.display()))?
+        }
+        Some(supervisely_storage) => {
+            let ann_path = PathBuf::from(format!("{:0>6}.pcd.json", index.to_string()));
+            kitti_format::read_supervisely_from_storage(supervisely_storage, &ann_path)
+                .with_context(|| format!("unable to read {}", ann_path.display()))?
+        }
+    };
+    Ok(objects)
+}
+
+/// Loads the point cloud of a frame. With extra LiDARs, the scans of
+/// the other devices are moved into the frame of [PRIMARY_LIDAR]
+/// through the vehicle frame using `extrinsics/<dir>.txt` and merged,
+/// and the index of each device, starting with [PRIMARY_LIDAR] as `0`,
+/// is stored per point. Every extra LiDAR needs an extrinsic file,
+/// while [PRIMARY_LIDAR] without one sits at the vehicle origin.
+///
+/// If the mask directory has a `<index>.bin` file for the frame, it
+/// replaces any [ACTIVE] field of the [PRIMARY_LIDAR] scan. It holds one
+/// byte per point of that scan, where `0` marks an inactive point. The
+/// points of other LiDARs without an [ACTIVE] field are active.
+///
+/// A SemanticKITTI `labels/<index>.label` file adds the [SEMANTIC] and
+/// [INSTANCE] channels to the [PRIMARY_LIDAR] scan. The points of other
+/// LiDARs are unlabeled.
+pub fn load_frame_cloud(
+    index: i32,
+    storage: &dyn Storage,
+    config: &CloudConfig,
+) -> Result<PointCloud> {
+    let CloudConfig {
+        format,
+        ref lidars,
+        ref mask_dir,
+        ..
+    } = *config;
+
+    let load = |pcd_dir: &Path| -> Result<PointCloud> {
+        let pcd_path = find_scan_path(storage, pcd_dir, index, format)?;
+        load_cloud(storage, &pcd_path, format)
+            .with_context(|| format!("unable to read {}", pcd_path.display()))
+    };
+
+    let mut cloud = load(Path::new(PRIMARY_LIDAR))?;
+
+    if let Some(mask_dir) = mask_dir {
+        let mask_path = mask_dir.join(format!("{index:0>6}.bin"));
+        if storage.exists(&mask_path) {
+            let mask = read_decoded(storage, &mask_path)?;
+            cloud
+                .insert_channel(ACTIVE, Channel::U8(mask))
+                .with_context(|| format!("mismatched mask {}", mask_path.display()))?;
         } else {
-            kitti_format::read_ann_file(ann_path, &calib, &exclude_classes)
+            static WARN: Once = Once::new();
+            WARN.call_once(|| {
+                eprintln!(
+                    "Warning: no mask {} for frame {index}, further missing masks are not reported",
+                    mask_path.display()
+                );
+            });
         }
-    } else {
-        let ann_path = supervisely_ann_dir
-            .as_ref()
-            .unwrap()
-            .join(format!("{:0>6}.pcd.json", index.to_string()));
-        let objects: Vec<KittiObject> = kitti_format::read_from_supervisely(&ann_path);
-        objects
+    }
+
+    let label_path = Path::new("labels").join(format!("{index:0>6}.label"));
+    if storage.exists(&label_path) {
+        let labels = kitti_format::read_semantic_labels_from_storage(storage, &label_path)
+            .with_context(|| format!("unable to read {}", label_path.display()))?;
+        let (semantic, instance) = labels
+            .iter()
+            .map(|label| (label.semantic, label.instance))
+            .unzip();
+        cloud
+            .insert_channel(SEMANTIC, Channel::U16(semantic))
+            .with_context(|| format!("mismatched labels {}", label_path.display()))?;
+        cloud.insert_channel(INSTANCE, Channel::U16(instance))?;
+    }
+
+    if !lidars.is_empty() {
+        let vehicle_to_primary = load_extrinsic(storage, PRIMARY_LIDAR)?
+            .unwrap_or_else(LidarExtrinsic::identity)
+            .to_isometry()
+            .inverse();
+        let others = lidars
+            .iter()
+            .map(|lidar| {
+                let extrinsic = load_extrinsic(storage, lidar)?.ok_or_else(|| {
+                    anyhow!("no extrinsics/{lidar}.txt for LiDAR {lidar} in {storage}")
+                })?;
+                let lidar_to_primary = vehicle_to_primary * extrinsic.to_isometry();
+                Ok(load(Path::new(lidar))?.transform(&lidar_to_primary.cast()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut clouds: Vec<_> = iter::once(cloud).chain(others).collect();
+        fill_missing_channel(&mut clouds, ACTIVE, 1.0)?;
+        fill_missing_channel(&mut clouds, SEMANTIC, 0.0)?;
+        fill_missing_channel(&mut clouds, INSTANCE, 0.0)?;
+        cloud = PointCloud::merge_devices(clouds)?;
+    }
+
+    Ok(cloud)
+}
+
+/// Gives the clouds without a channel that another cloud has one with
+/// the same value for every point, so that
+/// [PointCloud::merge_devices] keeps the channel.
+fn fill_missing_channel(clouds: &mut [PointCloud], name: &str, value: f64) -> Result<()> {
+    let Some(kind) = clouds
+        .iter()
+        .find_map(|cloud| cloud.channel(name))
+        .map(Channel::kind)
+    else {
+        return Ok(());
     };
-    objects
+    for cloud in clouds
+        .iter_mut()
+        .filter(|cloud| cloud.channel(name).is_none())
+    {
+        let mut channel = Channel::new(kind);
+        for _ in 0..cloud.len() {
+            channel.push(value);
+        }
+        cloud.insert_channel(name, channel)?;
+    }
+    Ok(())
+}
+
+/// Reads the extrinsic of a LiDAR from `extrinsics/<lidar>.txt`, or
+/// `None` if there is no such file.
+fn load_extrinsic(storage: &dyn Storage, lidar: &str) -> Result<Option<LidarExtrinsic>> {
+    let path = Path::new("extrinsics").join(format!("{lidar}.txt"));
+    if !storage.exists(&path) {
+        return Ok(None);
+    }
+    let extrinsic = LidarExtrinsic::from_storage(storage, &path)
+        .with_context(|| format!("unable to read {}", path.display()))?;
+    Ok(Some(extrinsic))
+}
+
+/// Reads the road plane of a frame from `planes/<index>.txt` and moves
+/// it to the velodyne frame.
+pub fn load_ground_plane(index: i32, storage: &dyn Storage) -> Result<Option<GroundPlane>> {
+    let plane_path = Path::new("planes").join(format!("{index:0>6}.txt"));
+    if !storage.exists(&plane_path) {
+        return Ok(None);
+    }
+    let calib = load_calib(index, storage)?;
+    let plane = GroundPlane::from_storage(storage, &plane_path)
+        .with_context(|| format!("unable to read {}", plane_path.display()))?;
+    Ok(Some(plane.to_velodyne(&calib)))
+}
+
+/// Reads the `image_2` picture of a frame if both it and the calibration
+/// exist, along with the `image_3` picture, the disparity map and the
+/// depth map if present.
+pub fn load_camera_frame(
+    index: i32,
+    storage: &dyn Storage,
+    config: &CloudConfig,
+) -> Result<Option<CameraFrame>> {
+    let file_name = format!("{index:0>6}.png");
+    let image_path = Path::new(KittiCamera::Cam2.image_dir()).join(&file_name);
+    let calib_path = Path::new("calib").join(format!("{index:0>6}.txt"));
+    if !storage.exists(&image_path) || !storage.exists(&calib_path) {
+        return Ok(None);
+    }
+
+    let right_path = Path::new(KittiCamera::Cam3.image_dir()).join(&file_name);
+    let right_image = storage
+        .exists(&right_path)
+        .then(|| load_image(storage, &right_path))
+        .transpose()?;
+
+    let load_map = |dir: &Option<PathBuf>| -> Result<Option<PixelMap>> {
+        let Some(path) = dir.as_ref().map(|dir| dir.join(&file_name)) else {
+            return Ok(None);
+        };
+        if !storage.exists(&path) {
+            return Ok(None);
+        }
+        let map = PixelMap::from_storage(storage, &path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+        Ok(Some(map))
+    };
+
+    Ok(Some(CameraFrame {
+        image: load_image(storage, &image_path)?,
+        calib: load_calib(index, storage)?,
+        right_image,
+        disparity: load_map(&config.disparity_dir)?,
+        depth: load_map(&config.depth_dir)?,
+    }))
+}
+
+impl FrameData {
+    /// Finds the boxes the points lie in with the given margins, reusing
+    /// the membership found on load if they are unchanged.
+    pub fn membership(&self, box_margins: &BoxMargins) -> Cow<'_, BoxMembership> {
+        if *box_margins == self.box_margins {
+            Cow::Borrowed(&self.membership)
+        } else {
+            Cow::Owned(box_membership(&self.objects, &self.cloud, box_margins))
+        }
+    }
+}
+
+fn box_membership(
+    objects: &[KittiObject],
+    cloud: &PointCloud,
+    box_margins: &BoxMargins,
+) -> BoxMembership {
+    BoxIndex::new(
+        objects
+            .iter()
+            .map(|obj| (&obj.bbox3d, box_margins.of(&obj.class))),
+    )
+    .membership(cloud.points())
 }
 
 pub fn get_new_frame_data(
     index: i32,
-    kitti_dir: &Path,
-    supervisely_ann_dir: Option<&Path>,
+    storage: &dyn Storage,
+    supervisely_storage: Option<&dyn Storage>,
     pcd_format: PcdFormat,
+    cloud_config: &CloudConfig,
+    box_margins: &BoxMargins,
 ) -> Result<FrameData> {
-    let pcd_dir = kitti_dir.join("velodyne");
-    let objects = get_objects_from_frame_id(index, kitti_dir, supervisely_ann_dir, pcd_format);
+    let objects = get_objects_from_frame_id(index, storage, supervisely_storage, pcd_format)?;
     // let objects = index_to_objects.get(&index.unwrap()).unwrap();
     // Get the pcd file
-    let pcd_path = pcd_dir.join(format!("{:0>6}.bin", index.to_string()));
-    let info_points =
-        load_bin(&pcd_path).with_context(|| format!("unable to read {}", pcd_path.display()))?;
-    let points_in_range: Vec<_> = info_points
-        .iter()
-        .filter(|p| {
-            in_bbox(
-                &na::Point3::from([p.point.x, p.point.y, p.point.z]).cast(),
-                &objects,
-            )
-        })
-        .map(|p| (*p).clone())
-        .collect();
-    let points_out_range: Vec<_> = info_points
-        .iter()
-        .filter(|p| {
-            !in_bbox(
-                &na::Point3::from([p.point.x, p.point.y, p.point.z]).cast(),
-                &objects,
-            )
-        })
-        .map(|p| (*p).clone())
-        .collect();
-    let num_points_map: Vec<usize> = objects
-        .iter()
-        .map(|obj| {
-            let num_points = info_points
-                .iter()
-                .filter(|p| {
-                    in_bbox(
-                        &na::Point3::from([p.point.x, p.point.y, p.point.z]).cast(),
-                        &[obj.clone()],
-                    )
-                })
-                .count();
-            num_points
-        })
-        .collect();
+    let cloud = load_frame_cloud(index, storage, cloud_config)?;
+    let membership = box_membership(&objects, &cloud, box_margins);
+    let ground_plane = load_ground_plane(index, storage)?;
+    let camera = load_camera_frame(index, storage, cloud_config)?;
 
     Ok(FrameData {
         objects,
-        points_in_range,
-        points_out_range,
-        num_points_map,
+        cloud,
+        box_margins: box_margins.clone(),
+        membership,
+        ground_plane,
+        camera,
     })
 }
@@ -0,0 +1,175 @@
+use crate::point_cloud::{Channel, ChannelKind, PointCloud};
+use anyhow::Result;
+use std::io::prelude::*;
+
+/// The data encoding of written PCD files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PcdEncoding {
+    Ascii,
+    Binary,
+}
+
+/// Writes the positions and intensities as a KITTI `.bin` scan. Other
+/// channels are dropped.
+pub fn write_bin<W: Write>(mut writer: W, cloud: &PointCloud) -> Result<()> {
+    for point in cloud.points() {
+        for value in point {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a cloud as a PCD file with `x`, `y`, `z` and `intensity`
+/// fields, followed by one field per channel with the channel's type.
+/// The `r`, `g` and `b` channels are packed into a `U 4` PCL `rgb`
+/// field.
+pub fn write_pcd<W: Write>(mut writer: W, cloud: &PointCloud, encoding: PcdEncoding) -> Result<()> {
+    let columns = Columns::new(cloud);
+
+    let mut names = vec!["x", "y", "z", "intensity"];
+    let mut types = vec![pcd_type(ChannelKind::F32); 4];
+    for (name, channel) in &columns.scalars {
+        names.push(name);
+        types.push(pcd_type(channel.kind()));
+    }
+    if columns.rgb.is_some() {
+        names.push("rgb");
+        types.push(pcd_type(ChannelKind::U32));
+    }
+    let sizes: Vec<String> = types.iter().map(|(_, size)| size.to_string()).collect();
+    let letters: Vec<String> = types.iter().map(|(letter, _)| letter.to_string()).collect();
+
+    writeln!(writer, "# .PCD v0.7 - Point Cloud Data file format")?;
+    writeln!(writer, "VERSION 0.7")?;
+    writeln!(writer, "FIELDS {}", names.join(" "))?;
+    writeln!(writer, "SIZE {}", sizes.join(" "))?;
+    writeln!(writer, "TYPE {}", letters.join(" "))?;
+    writeln!(writer, "COUNT{}", " 1".repeat(names.len()))?;
+    writeln!(writer, "WIDTH {}", cloud.len())?;
+    writeln!(writer, "HEIGHT 1")?;
+    writeln!(writer, "VIEWPOINT 0 0 0 1 0 0 0")?;
+    writeln!(writer, "POINTS {}", cloud.len())?;
+
+    match encoding {
+        PcdEncoding::Ascii => {
+            writeln!(writer, "DATA ascii")?;
+            for (idx, &[x, y, z, intensity]) in cloud.points().iter().enumerate() {
+                write!(writer, "{x} {y} {z} {intensity}")?;
+                for (_, channel) in &columns.scalars {
+                    write!(writer, " ")?;
+                    channel.write_ascii(idx, &mut writer)?;
+                }
+                if let Some(rgb) = &columns.rgb {
+                    write!(writer, " {}", packed_rgb_at(rgb, idx))?;
+                }
+                writeln!(writer)?;
+            }
+        }
+        PcdEncoding::Binary => {
+            writeln!(writer, "DATA binary")?;
+            for (idx, point) in cloud.points().iter().enumerate() {
+                for value in point {
+                    writer.write_all(&value.to_le_bytes())?;
+                }
+                for (_, channel) in &columns.scalars {
+                    channel.write_le(idx, &mut writer)?;
+                }
+                if let Some(rgb) = &columns.rgb {
+                    writer.write_all(&packed_rgb_at(rgb, idx).to_le_bytes())?;
+                }
+            }
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes a cloud as a binary little-endian PLY file. The `r`, `g` and
+/// `b` channels become `uchar` `red`, `green` and `blue` properties and
+/// the other channels keep their types.
+pub fn write_ply<W: Write>(mut writer: W, cloud: &PointCloud) -> Result<()> {
+    let columns = Columns::new(cloud);
+
+    writeln!(writer, "ply")?;
+    writeln!(writer, "format binary_little_endian 1.0")?;
+    writeln!(writer, "element vertex {}", cloud.len())?;
+    for name in ["x", "y", "z", "intensity"] {
+        writeln!(writer, "property float {name}")?;
+    }
+    for (name, channel) in &columns.scalars {
+        writeln!(writer, "property {} {name}", ply_type(channel.kind()))?;
+    }
+    if columns.rgb.is_some() {
+        for name in ["red", "green", "blue"] {
+            writeln!(writer, "property uchar {name}")?;
+        }
+    }
+    writeln!(writer, "end_header")?;
+
+    for (idx, point) in cloud.points().iter().enumerate() {
+        for value in point {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for (_, channel) in &columns.scalars {
+            channel.write_le(idx, &mut writer)?;
+        }
+        if let Some(rgb) = &columns.rgb {
+            writer.write_all(&rgb_at(rgb, idx))?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+fn pcd_type(kind: ChannelKind) -> (char, usize) {
+    let letter = match kind {
+        ChannelKind::I8 | ChannelKind::I16 | ChannelKind::I32 => 'I',
+        ChannelKind::U8 | ChannelKind::U16 | ChannelKind::U32 => 'U',
+        ChannelKind::F32 | ChannelKind::F64 => 'F',
+    };
+    (letter, kind.size())
+}
+
+fn ply_type(kind: ChannelKind) -> &'static str {
+    match kind {
+        ChannelKind::I8 => "char",
+        ChannelKind::U8 => "uchar",
+        ChannelKind::I16 => "short",
+        ChannelKind::U16 => "ushort",
+        ChannelKind::I32 => "int",
+        ChannelKind::U32 => "uint",
+        ChannelKind::F32 => "float",
+        ChannelKind::F64 => "double",
+    }
+}
+
+/// Channels split into plain scalars and the colour channels.
+struct Columns<'a> {
+    scalars: Vec<(&'a str, &'a Channel)>,
+    rgb: Option<[&'a Channel; 3]>,
+}
+
+impl<'a> Columns<'a> {
+    fn new(cloud: &'a PointCloud) -> Self {
+        let rgb = cloud.rgb();
+        let scalars = cloud
+            .channels()
+            .filter(|(name, _)| rgb.is_none() || !["r", "g", "b"].contains(name))
+            .collect();
+        Self { scalars, rgb }
+    }
+}
+
+fn rgb_at(rgb: &[&Channel; 3], idx: usize) -> [u8; 3] {
+    rgb.map(|channel| channel.get(idx).clamp(0.0, 255.0) as u8)
+}
+
+/// Packs the colour as PCL does, i.e. `0x00RRGGBB`.
+fn packed_rgb_at(rgb: &[&Channel; 3], idx: usize) -> u32 {
+    let [r, g, b] = rgb_at(rgb, idx);
+    u32::from_be_bytes([0, r, g, b])
+}